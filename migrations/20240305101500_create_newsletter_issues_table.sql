@@ -0,0 +1,11 @@
+-- Create Newsletter Issues Table
+CREATE TABLE newsletter_issues(
+    newsletter_issue_id uuid NOT NULL,
+    PRIMARY KEY (newsletter_issue_id),
+    title TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    tracking_enabled BOOLEAN NOT NULL,
+    recipients INTEGER NOT NULL DEFAULT 0,
+    published_at timestamptz NOT NULL
+);
@@ -0,0 +1,12 @@
+-- Create Newsletter Issue Events Table
+CREATE TABLE newsletter_issue_events(
+    newsletter_issue_id uuid NOT NULL
+    REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_id uuid NOT NULL
+    REFERENCES subscriptions (id),
+    kind TEXT NOT NULL,
+    url TEXT NULL,
+    occurred_at timestamptz NOT NULL
+);
+CREATE INDEX newsletter_issue_events_issue_kind_idx
+    ON newsletter_issue_events (newsletter_issue_id, kind);
@@ -0,0 +1,9 @@
+BEGIN;
+ALTER TABLE newsletter_issues ADD COLUMN slug TEXT NULL;
+-- Backfill `slug` for issues published before the archive existed
+UPDATE newsletter_issues
+SET slug = newsletter_issue_id::text
+WHERE slug IS NULL;
+ALTER TABLE newsletter_issues ALTER COLUMN slug SET NOT NULL;
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_slug_key UNIQUE (slug);
+COMMIT;
@@ -0,0 +1,12 @@
+-- Create Email Delivery Queue Table
+CREATE TABLE email_delivery_queue(
+    email_id uuid NOT NULL,
+    PRIMARY KEY (email_id),
+    recipient TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    html_body TEXT NOT NULL,
+    text_body TEXT NOT NULL,
+    n_retries INTEGER NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL,
+    created_at timestamptz NOT NULL
+);
@@ -0,0 +1,4 @@
+-- The delivery queue becomes a transactional outbox with dead-lettering
+ALTER TABLE email_delivery_queue RENAME TO email_outbox;
+ALTER TABLE email_outbox ADD COLUMN status TEXT NOT NULL DEFAULT 'pending';
+ALTER TABLE email_outbox ADD COLUMN last_error TEXT NULL;
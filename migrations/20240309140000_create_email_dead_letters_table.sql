@@ -0,0 +1,23 @@
+-- Emails that exhausted their delivery attempts move out of the outbox
+CREATE TABLE email_dead_letters(
+    email_id uuid NOT NULL,
+    PRIMARY KEY (email_id),
+    recipient TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    html_body TEXT NOT NULL,
+    text_body TEXT NOT NULL,
+    n_attempts INTEGER NOT NULL,
+    last_error TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    failed_at timestamptz NOT NULL
+);
+INSERT INTO email_dead_letters (
+    email_id, recipient, subject, html_body, text_body,
+    n_attempts, last_error, created_at, failed_at
+)
+SELECT email_id, recipient, subject, html_body, text_body,
+    n_retries, COALESCE(last_error, ''), created_at, now()
+FROM email_outbox
+WHERE status = 'dead';
+DELETE FROM email_outbox WHERE status = 'dead';
+ALTER TABLE email_outbox DROP COLUMN status;
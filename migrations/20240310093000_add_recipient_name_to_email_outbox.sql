@@ -0,0 +1,3 @@
+-- Keep the recipient's display name alongside the address
+ALTER TABLE email_outbox ADD COLUMN recipient_name TEXT NULL;
+ALTER TABLE email_dead_letters ADD COLUMN recipient_name TEXT NULL;
@@ -0,0 +1,3 @@
+-- The W3C trace context of the request that queued the email, so that its
+-- delivery shows up in the same trace.
+ALTER TABLE email_outbox ADD COLUMN traceparent TEXT NULL;
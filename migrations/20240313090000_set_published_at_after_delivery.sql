@@ -0,0 +1,3 @@
+-- An issue is only published, i.e. listed in the archive and the feed, once
+-- it has been delivered. Until then `published_at` is NULL.
+ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
@@ -0,0 +1,4 @@
+-- The sender, headers and attachments of a queued newsletter issue, as JSON.
+-- Confirmation emails have none.
+ALTER TABLE email_outbox ADD COLUMN options TEXT NULL;
+ALTER TABLE email_dead_letters ADD COLUMN options TEXT NULL;
@@ -0,0 +1,4 @@
+-- The archive and the feed list published issues, newest first.
+CREATE INDEX newsletter_issues_published_at_idx
+ON newsletter_issues (published_at DESC)
+WHERE published_at IS NOT NULL;
@@ -0,0 +1,128 @@
+use actix_web::http::header::HeaderMap;
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use std::{error::Error, fmt::Formatter};
+use uuid::Uuid;
+
+#[derive(Debug)]
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+fn error_chain_fmt(e: &impl Error, f: &mut Formatter<'_>) -> std::fmt::Result {
+    writeln!(f, "{}\n", e)?;
+    let mut current = e.source();
+    while let Some(cause) = current {
+        writeln!(f, "Caused by:\n\t{}", cause)?;
+        current = cause.source();
+    }
+    Ok(())
+}
+
+pub enum AuthError {
+    InvalidCredentials(String),
+    DatabaseError(sqlx::Error),
+    Unexpected(String),
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::InvalidCredentials(e) => write!(f, "{}", e),
+            AuthError::DatabaseError(_) => write!(f, "Failed to look up the user credentials."),
+            AuthError::Unexpected(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for AuthError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AuthError::DatabaseError(e) => Some(e),
+            AuthError::InvalidCredentials(_) | AuthError::Unexpected(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Debug for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<String> for AuthError {
+    fn from(e: String) -> Self {
+        Self::InvalidCredentials(e)
+    }
+}
+
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, password_hash
+        FROM users
+        WHERE username = $1
+        "#,
+        credentials.username,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(AuthError::DatabaseError)?;
+
+    let (expected_password_hash, user_id) = match row {
+        Some(row) => (row.password_hash, row.user_id),
+        None => {
+            return Err(AuthError::InvalidCredentials(
+                "Unknown Username".to_string(),
+            ));
+        }
+    };
+
+    let expected_password_hash = PasswordHash::new(&expected_password_hash)
+        .map_err(|err| AuthError::Unexpected(err.to_string()))?;
+
+    Argon2::default()
+        .verify_password(
+            credentials.password.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .map_err(|_err| AuthError::InvalidCredentials("Invalid password".to_string()))?;
+
+    Ok(user_id)
+}
+
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, String> {
+    let header_value = headers
+        .get("Authorization")
+        .ok_or("The 'Authorization' header was missing")?
+        .to_str()
+        .map_err(|_err| "The 'Authorization' header was not a valid UTF8 string.".to_string())?;
+
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .ok_or("The authorization scheme was not 'Basic'.")?;
+    let decoded_bytes = base64::decode_config(base64encoded_segment, base64::STANDARD)
+        .map_err(|_err| "Failed to base64-decode 'Basic' credentials.".to_string())?;
+
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .map_err(|_err| "The decoded credential string is not valid UTF8.".to_string())?;
+
+    let mut credentials = decoded_credentials.splitn(2, ":");
+
+    let username = credentials
+        .next()
+        .ok_or_else(|| "A username must be provided in 'Basic' auth.".to_string())?
+        .to_string();
+    let password = credentials
+        .next()
+        .ok_or_else(|| "A password must be provided in 'Basic' auth.".to_string())?
+        .to_string();
+    let password = Secret::new(password);
+
+    Ok(Credentials { username, password })
+}
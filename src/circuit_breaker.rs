@@ -0,0 +1,191 @@
+use crate::configuration::CircuitBreakerSettings;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+enum State {
+    Closed { consecutive_failures: u32 },
+    Open { until: Instant },
+    HalfOpen { next_probe_at: Instant },
+}
+
+/// Stops calling a failing dependency after `failure_threshold` consecutive
+/// failures, then lets a single probe through once `open_duration` elapsed.
+pub struct CircuitBreaker {
+    state: Mutex<State>,
+    failure_threshold: u32,
+    open_duration: Duration,
+}
+
+impl CircuitBreaker {
+    pub fn new(settings: &CircuitBreakerSettings) -> Self {
+        Self {
+            state: Mutex::new(State::Closed {
+                consecutive_failures: 0,
+            }),
+            failure_threshold: settings.failure_threshold.max(1),
+            open_duration: settings.open_duration(),
+        }
+    }
+
+    /// Returns `false` when the call must fail fast without reaching the dependency.
+    /// While half-open a single probe is let through per `open_duration`, so a
+    /// probe that never reports back cannot wedge the circuit.
+    pub fn try_acquire(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match *state {
+            State::Closed { .. } => true,
+            State::Open { until }
+            | State::HalfOpen {
+                next_probe_at: until,
+            } if now >= until => {
+                tracing::info!("Circuit breaker is half-open, probing the email provider.");
+                *state = State::HalfOpen {
+                    next_probe_at: now + self.open_duration,
+                };
+                true
+            }
+            State::Open { .. } | State::HalfOpen { .. } => false,
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let State::HalfOpen { .. } = *state {
+            tracing::info!("Circuit breaker closed, the email provider recovered.");
+        }
+        *state = State::Closed {
+            consecutive_failures: 0,
+        };
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        let trip = match *state {
+            State::Closed {
+                ref mut consecutive_failures,
+            } => {
+                *consecutive_failures += 1;
+                *consecutive_failures >= self.failure_threshold
+            }
+            State::HalfOpen { .. } => true,
+            State::Open { .. } => false,
+        };
+        if trip {
+            tracing::warn!(
+                "Circuit breaker opened, failing email requests fast for {:?}.",
+                self.open_duration
+            );
+            *state = State::Open {
+                until: Instant::now() + self.open_duration,
+            };
+        }
+    }
+
+    /// How long until a call may go through again: zero unless the circuit
+    /// is open, or half-open with its probe already in flight.
+    pub fn retry_after(&self) -> Duration {
+        match *self.state.lock().unwrap() {
+            State::Closed { .. } => Duration::ZERO,
+            State::Open { until }
+            | State::HalfOpen {
+                next_probe_at: until,
+            } => until.saturating_duration_since(Instant::now()),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        match *self.state.lock().unwrap() {
+            State::Closed { .. } => CircuitState::Closed,
+            State::Open { until } if Instant::now() < until => CircuitState::Open,
+            State::Open { .. } | State::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::circuit_breaker::{CircuitBreaker, CircuitState};
+    use crate::configuration::CircuitBreakerSettings;
+    use std::time::Duration;
+
+    fn circuit_breaker() -> CircuitBreaker {
+        CircuitBreaker::new(&CircuitBreakerSettings {
+            failure_threshold: 3,
+            open_duration_milliseconds: 1000,
+        })
+    }
+
+    fn trip(circuit_breaker: &CircuitBreaker) {
+        for _ in 0..3 {
+            assert!(circuit_breaker.try_acquire());
+            circuit_breaker.record_failure();
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_circuit_opens_after_consecutive_failures() {
+        let circuit_breaker = circuit_breaker();
+        trip(&circuit_breaker);
+
+        assert_eq!(CircuitState::Open, circuit_breaker.state());
+        assert!(!circuit_breaker.try_acquire());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_success_resets_the_failure_count() {
+        let circuit_breaker = circuit_breaker();
+        circuit_breaker.record_failure();
+        circuit_breaker.record_failure();
+        circuit_breaker.record_success();
+        circuit_breaker.record_failure();
+
+        assert_eq!(CircuitState::Closed, circuit_breaker.state());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_single_probe_is_allowed_once_the_open_duration_elapsed() {
+        let circuit_breaker = circuit_breaker();
+        trip(&circuit_breaker);
+        tokio::time::advance(Duration::from_millis(1001)).await;
+
+        assert_eq!(CircuitState::HalfOpen, circuit_breaker.state());
+        assert!(circuit_breaker.try_acquire());
+        assert!(!circuit_breaker.try_acquire());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_successful_probe_closes_the_circuit() {
+        let circuit_breaker = circuit_breaker();
+        trip(&circuit_breaker);
+        tokio::time::advance(Duration::from_millis(1001)).await;
+
+        assert!(circuit_breaker.try_acquire());
+        circuit_breaker.record_success();
+
+        assert_eq!(CircuitState::Closed, circuit_breaker.state());
+        assert!(circuit_breaker.try_acquire());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_failed_probe_reopens_the_circuit() {
+        let circuit_breaker = circuit_breaker();
+        trip(&circuit_breaker);
+        tokio::time::advance(Duration::from_millis(1001)).await;
+
+        assert!(circuit_breaker.try_acquire());
+        circuit_breaker.record_failure();
+
+        assert_eq!(CircuitState::Open, circuit_breaker.state());
+        assert!(!circuit_breaker.try_acquire());
+    }
+}
@@ -1,21 +1,140 @@
-use crate::domain::SubscriberEmail;
+use crate::{
+    domain::{Mailbox, SubscriberEmail, SubscriberName},
+    email_client::EmailClient,
+};
 use config::{Config, File, FileFormat};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions, PgSslMode};
+use std::collections::HashMap;
+use std::path::Path;
 
 #[derive(serde::Deserialize, Debug, Clone)]
 pub struct EmailClientSettings {
     pub base_url: String,
     pub sender_email: String,
-    pub authorization_token: String,
+    pub sender_name: Option<String>,
+    /// Alternative senders that a newsletter issue can pick by key.
+    #[serde(default)]
+    pub sender_identities: HashMap<String, SenderIdentitySettings>,
+    pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    pub batch_enabled: bool,
+    #[serde(default)]
+    pub rate_limit: RateLimitSettings,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerSettings,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct SenderIdentitySettings {
+    pub email: String,
+    pub name: Option<String>,
+}
+
+impl SenderIdentitySettings {
+    pub fn parse(&self) -> Result<Mailbox, String> {
+        parse_mailbox(&self.email, self.name.as_deref())
+    }
+}
+
+fn parse_mailbox(email: &str, name: Option<&str>) -> Result<Mailbox, String> {
+    let email = SubscriberEmail::parse(email.to_owned())?;
+    let name = name
+        .map(|name| SubscriberName::parse(name.to_owned()))
+        .transpose()?;
+    Ok(Mailbox::new(email, name))
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct CircuitBreakerSettings {
+    pub failure_threshold: u32,
+    pub open_duration_milliseconds: u64,
+}
+
+impl CircuitBreakerSettings {
+    pub fn open_duration(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.open_duration_milliseconds)
+    }
+}
+
+impl Default for CircuitBreakerSettings {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_duration_milliseconds: 30_000,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct RateBudget {
+    pub per_second: Option<u32>,
+    pub per_hour: Option<u32>,
+}
+
+/// Outbound email budgets. Missing budgets are unlimited.
+#[derive(serde::Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct RateLimitSettings {
+    pub per_second: Option<u32>,
+    pub per_hour: Option<u32>,
+    #[serde(default)]
+    pub per_domain: HashMap<String, RateBudget>,
+}
+
+impl RateLimitSettings {
+    pub fn global(&self) -> RateBudget {
+        RateBudget {
+            per_second: self.per_second,
+            per_hour: self.per_hour,
+        }
+    }
 }
 
 impl EmailClientSettings {
-    pub fn sender(&self) -> Result<SubscriberEmail, String> {
-        SubscriberEmail::parse(self.sender_email.clone())
+    pub fn sender(&self) -> Result<Mailbox, String> {
+        parse_mailbox(&self.sender_email, self.sender_name.as_deref())
     }
+
+    /// Parses every configured identity, reporting all the malformed ones.
+    pub fn sender_identities(&self) -> Result<HashMap<String, Mailbox>, String> {
+        let mut identities = HashMap::new();
+        let mut errors = Vec::new();
+        for (key, identity) in &self.sender_identities {
+            match identity.parse() {
+                Ok(mailbox) => {
+                    identities.insert(key.clone(), mailbox);
+                }
+                Err(e) => errors.push(format!("Invalid sender identity '{}': {}", key, e)),
+            }
+        }
+        if errors.is_empty() {
+            Ok(identities)
+        } else {
+            errors.sort();
+            Err(errors.join(" "))
+        }
+    }
+
     pub fn timeout(&self) -> std::time::Duration {
         std::time::Duration::from_millis(self.timeout_milliseconds)
     }
+    pub fn client(self) -> Result<EmailClient, String> {
+        let sender = self
+            .sender()
+            .map_err(|e| format!("Invalid sender: {}", e))?;
+        let sender_identities = self.sender_identities()?;
+        let timeout = self.timeout();
+        Ok(EmailClient::new(
+            self.base_url,
+            sender,
+            self.authorization_token,
+            timeout,
+            self.batch_enabled,
+            &self.rate_limit,
+            &self.circuit_breaker,
+        )
+        .with_sender_identities(sender_identities))
+    }
 }
 
 #[derive(serde::Deserialize, Debug, Clone)]
@@ -23,29 +142,306 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    /// How long in-flight requests and deliveries get to finish once the
+    /// process is asked to terminate.
+    pub shutdown_timeout_seconds: u64,
+    /// Apply pending migrations when the application starts.
+    #[serde(default)]
+    pub run_migrations: bool,
+}
+
+impl ApplicationSettings {
+    pub fn shutdown_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.shutdown_timeout_seconds)
+    }
 }
 
 #[derive(serde::Deserialize, Debug, Clone)]
 pub struct DatabaseSettings {
     pub username: String,
-    pub password: String,
+    pub password: Secret<String>,
     pub host: String,
     pub port: u16,
     pub database_name: String,
+    /// Refuse plaintext connections. Ignored when `ssl_mode` is set.
+    #[serde(default)]
+    pub require_ssl: bool,
+    pub ssl_mode: Option<SslMode>,
+    /// The CA used to verify the server certificate, for `verify-ca` and `verify-full`.
+    pub ca_certificate_path: Option<String>,
+    #[serde(default)]
+    pub pool: PoolSettings,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SslMode {
+    Disable,
+    Allow,
+    Prefer,
+    Require,
+    VerifyCa,
+    VerifyFull,
+}
+
+impl From<SslMode> for PgSslMode {
+    fn from(mode: SslMode) -> Self {
+        match mode {
+            SslMode::Disable => PgSslMode::Disable,
+            SslMode::Allow => PgSslMode::Allow,
+            SslMode::Prefer => PgSslMode::Prefer,
+            SslMode::Require => PgSslMode::Require,
+            SslMode::VerifyCa => PgSslMode::VerifyCa,
+            SslMode::VerifyFull => PgSslMode::VerifyFull,
+        }
+    }
+}
+
+/// Connection pool tuning. The defaults match sqlx's own.
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PoolSettings {
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout_milliseconds: u64,
+    /// Close connections that sat unused for this long. `None` keeps them open.
+    pub idle_timeout_seconds: Option<u64>,
+    /// Abort any statement running longer than this. `None` means no limit.
+    pub statement_timeout_milliseconds: Option<u64>,
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        Self {
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout_milliseconds: 30_000,
+            idle_timeout_seconds: Some(600),
+            statement_timeout_milliseconds: None,
+        }
+    }
 }
 
 impl DatabaseSettings {
-    pub fn connection_string(&self) -> String {
-        format!(
-            "postgres://{}:{}@{}:{}/{}",
-            self.username, self.password, self.host, self.port, self.database_name
-        )
+    pub fn ssl_mode(&self) -> PgSslMode {
+        match self.ssl_mode {
+            Some(mode) => mode.into(),
+            None if self.require_ssl => PgSslMode::Require,
+            None => PgSslMode::Prefer,
+        }
     }
-    pub fn connection_string_without_db(&self) -> String {
-        format!(
-            "postgres://{}:{}@{}:{}",
-            self.username, self.password, self.host, self.port
-        )
+
+    pub fn without_db(&self) -> PgConnectOptions {
+        let mut options = PgConnectOptions::new()
+            .host(&self.host)
+            .username(&self.username)
+            .password(self.password.expose_secret())
+            .port(self.port)
+            .ssl_mode(self.ssl_mode());
+        if let Some(path) = &self.ca_certificate_path {
+            options = options.ssl_root_cert(path);
+        }
+        if let Some(timeout) = self.pool.statement_timeout_milliseconds {
+            options = options.options([("statement_timeout", timeout.to_string())]);
+        }
+        options
+    }
+
+    pub fn with_db(&self) -> PgConnectOptions {
+        self.without_db().database(&self.database_name)
+    }
+
+    pub fn pool_options(&self) -> PgPoolOptions {
+        PgPoolOptions::new()
+            .max_connections(self.pool.max_connections)
+            .min_connections(self.pool.min_connections)
+            .acquire_timeout(std::time::Duration::from_millis(
+                self.pool.acquire_timeout_milliseconds,
+            ))
+            .idle_timeout(
+                self.pool
+                    .idle_timeout_seconds
+                    .map(std::time::Duration::from_secs),
+            )
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct TrackingSettings {
+    pub enabled: bool,
+    /// Not in `base.yaml`: every profile but `local` and `test` has to
+    /// provide its own, e.g. with `APP_TRACKING__SIGNING_KEY`.
+    #[serde(default = "unset_secret")]
+    pub signing_key: Secret<String>,
+}
+
+fn unset_secret() -> Secret<String> {
+    Secret::new(String::new())
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct EmailOutboxSettings {
+    pub max_attempts: u32,
+    pub poll_interval_milliseconds: u64,
+}
+
+impl EmailOutboxSettings {
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.poll_interval_milliseconds)
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Bunyan-compatible JSON, one object per line.
+    #[default]
+    Bunyan,
+    /// Multi-line, human-readable output for local development.
+    Pretty,
+    /// Compact `key=value` lines.
+    Logfmt,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+/// How email addresses and names are written to logs and spans.
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactionPolicy {
+    /// A truncated HMAC-SHA256 keyed with `log.redaction_key`, so that one
+    /// person's entries can still be correlated without revealing who they are.
+    #[default]
+    Hash,
+    /// Only the first character is kept, and the domain of email addresses.
+    Mask,
+    /// The value is replaced by a fixed placeholder.
+    Drop,
+}
+
+/// A log file written alongside stdout, rotated by appending the date to
+/// `file_name_prefix`.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct LogFileSettings {
+    pub directory: String,
+    pub file_name_prefix: String,
+    #[serde(default)]
+    pub rotation: LogRotation,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct LogSettings {
+    /// An `EnvFilter` directive, e.g. `info,sqlx=warn`. `RUST_LOG` takes
+    /// precedence over it.
+    pub filter: String,
+    /// Levels for individual modules, e.g. `sqlx: warn`, added to `filter`.
+    #[serde(default)]
+    pub modules: HashMap<String, String>,
+    #[serde(default)]
+    pub format: LogFormat,
+    #[serde(default)]
+    pub file: Option<LogFileSettings>,
+    #[serde(default)]
+    pub redaction: RedactionPolicy,
+    /// Not in `base.yaml`, like `tracking.signing_key`.
+    #[serde(default = "unset_secret")]
+    pub redaction_key: Secret<String>,
+}
+
+impl LogSettings {
+    /// `filter` followed by the module levels, as a single `EnvFilter` directive.
+    pub fn directives(&self) -> String {
+        let mut modules: Vec<_> = self
+            .modules
+            .iter()
+            .map(|(module, level)| format!("{}={}", module, level))
+            .collect();
+        modules.sort();
+        std::iter::once(self.filter.clone())
+            .chain(modules)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            filter: "info".to_owned(),
+            modules: HashMap::new(),
+            format: LogFormat::default(),
+            file: None,
+            redaction: RedactionPolicy::default(),
+            redaction_key: unset_secret(),
+        }
+    }
+}
+
+/// Whether a dependency is checked by `/health/ready`, and whether the
+/// application is unready when it is down.
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyCheck {
+    #[default]
+    Skip,
+    Report,
+    Require,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct HealthSettings {
+    #[serde(default)]
+    pub email_provider: DependencyCheck,
+    /// How long the delivery worker may go without polling the outbox
+    /// before it is considered stuck.
+    pub worker_heartbeat_timeout_milliseconds: u64,
+}
+
+impl HealthSettings {
+    pub fn worker_heartbeat_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.worker_heartbeat_timeout_milliseconds)
+    }
+}
+
+impl Default for HealthSettings {
+    fn default() -> Self {
+        Self {
+            email_provider: DependencyCheck::default(),
+            worker_heartbeat_timeout_milliseconds: 60_000,
+        }
+    }
+}
+
+/// Where to export traces. Traces are only exported when `enabled`.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct OtlpSettings {
+    pub enabled: bool,
+    /// The gRPC endpoint of an OpenTelemetry collector.
+    pub endpoint: String,
+    pub timeout_milliseconds: u64,
+}
+
+impl OtlpSettings {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+impl Default for OtlpSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: "http://localhost:4317".to_owned(),
+            timeout_milliseconds: 3000,
+        }
     }
 }
 
@@ -54,56 +450,763 @@ pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub email_outbox: EmailOutboxSettings,
+    pub tracking: TrackingSettings,
+    #[serde(default)]
+    pub log: LogSettings,
+    #[serde(default)]
+    pub otlp: OtlpSettings,
+    #[serde(default)]
+    pub health: HealthSettings,
+    /// The profile these settings were loaded from, set by
+    /// `get_configuration_for`.
+    #[serde(skip)]
+    pub environment: Option<Environment>,
 }
 
-pub enum Environment {
-    Local,
-    Production,
+/// Every problem found in a `Settings`, reported together.
+#[derive(Debug)]
+pub struct InvalidConfiguration(pub Vec<String>);
+
+impl std::fmt::Display for InvalidConfiguration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The configuration is invalid:")?;
+        for problem in &self.0 {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
 }
 
-impl Environment {
-    pub fn as_str(&self) -> &str {
-        match self {
-            Environment::Local => "local",
-            Environment::Production => "production",
+impl std::error::Error for InvalidConfiguration {}
+
+impl Settings {
+    /// Checks everything that would otherwise only fail once a request
+    /// exercises it, so a misconfigured instance refuses to start.
+    pub fn validate(&self) -> Result<(), InvalidConfiguration> {
+        let mut problems = Vec::new();
+        let mut check = |ok: bool, problem: &str| {
+            if !ok {
+                problems.push(problem.to_owned());
+            }
+        };
+
+        check(
+            !self.application.host.trim().is_empty(),
+            "application.host must not be empty.",
+        );
+        check(
+            is_http_url(&self.application.base_url),
+            "application.base_url must be an absolute http(s) URL, e.g. `https://example.com`.",
+        );
+
+        let database = &self.database;
+        check(database.port != 0, "database.port must not be 0.");
+        check(
+            !database.host.trim().is_empty(),
+            "database.host must not be empty.",
+        );
+        check(
+            !database.password.expose_secret().is_empty(),
+            "database.password must be set, e.g. with APP_DATABASE__PASSWORD.",
+        );
+        check(
+            database.pool.max_connections > 0,
+            "database.pool.max_connections must be at least 1.",
+        );
+        check(
+            database.pool.min_connections <= database.pool.max_connections,
+            "database.pool.min_connections must not exceed database.pool.max_connections.",
+        );
+        check(
+            database.pool.acquire_timeout_milliseconds > 0,
+            "database.pool.acquire_timeout_milliseconds must be greater than 0.",
+        );
+        check(
+            database.pool.statement_timeout_milliseconds != Some(0),
+            "database.pool.statement_timeout_milliseconds must be greater than 0, \
+            leave it unset to disable the timeout.",
+        );
+        if let Some(path) = &database.ca_certificate_path {
+            check(
+                std::path::Path::new(path).is_file(),
+                &format!(
+                    "database.ca_certificate_path `{}` is not a readable file.",
+                    path
+                ),
+            );
+        }
+
+        let email_client = &self.email_client;
+        check(
+            is_http_url(&email_client.base_url),
+            "email_client.base_url must be an absolute http(s) URL, \
+            e.g. `https://api.postmarkapp.com`.",
+        );
+        check(
+            !email_client.authorization_token.expose_secret().is_empty(),
+            "email_client.authorization_token must be set, \
+            e.g. with APP_EMAIL_CLIENT__AUTHORIZATION_TOKEN.",
+        );
+        check(
+            email_client.timeout_milliseconds > 0,
+            "email_client.timeout_milliseconds must be greater than 0.",
+        );
+        check(
+            email_client.circuit_breaker.open_duration_milliseconds > 0,
+            "email_client.circuit_breaker.open_duration_milliseconds must be greater than 0.",
+        );
+        let rate_limit = &email_client.rate_limit;
+        let budgets = std::iter::once(("email_client.rate_limit".to_owned(), rate_limit.global()))
+            .chain(rate_limit.per_domain.iter().map(|(domain, budget)| {
+                (
+                    format!("email_client.rate_limit.per_domain.{}", domain),
+                    budget.clone(),
+                )
+            }));
+        for (key, budget) in budgets {
+            for (period, limit) in [
+                ("per_second", budget.per_second),
+                ("per_hour", budget.per_hour),
+            ] {
+                check(
+                    limit != Some(0),
+                    &format!(
+                        "{}.{} must be at least 1, leave it unset for no limit.",
+                        key, period
+                    ),
+                );
+            }
+        }
+        if let Err(e) = email_client.sender() {
+            check(false, &format!("email_client.sender_email: {}", e));
+        }
+        if let Err(e) = email_client.sender_identities() {
+            check(false, &format!("email_client.sender_identities: {}", e));
+        }
+
+        check(
+            self.email_outbox.max_attempts > 0,
+            "email_outbox.max_attempts must be at least 1.",
+        );
+        check(
+            self.email_outbox.poll_interval_milliseconds > 0,
+            "email_outbox.poll_interval_milliseconds must be greater than 0.",
+        );
+        check(
+            self.health.worker_heartbeat_timeout_milliseconds
+                > self.email_outbox.poll_interval_milliseconds,
+            "health.worker_heartbeat_timeout_milliseconds must be longer than \
+            email_outbox.poll_interval_milliseconds.",
+        );
+        check(
+            !self.tracking.signing_key.expose_secret().is_empty(),
+            "tracking.signing_key must be set, e.g. with APP_TRACKING__SIGNING_KEY.",
+        );
+        if !self
+            .environment
+            .as_ref()
+            .is_some_and(Environment::allows_default_secrets)
+        {
+            for (key, secret, default) in [
+                ("database.password", &self.database.password, "password"),
+                (
+                    "email_client.authorization_token",
+                    &self.email_client.authorization_token,
+                    "my-secret-token",
+                ),
+                (
+                    "tracking.signing_key",
+                    &self.tracking.signing_key,
+                    "my-secret-tracking-key",
+                ),
+                (
+                    "log.redaction_key",
+                    &self.log.redaction_key,
+                    "my-secret-redaction-key",
+                ),
+            ] {
+                check(
+                    secret.expose_secret() != default,
+                    &format!(
+                        "{} is set to the development default, \
+                        which is only allowed in the local and test environments.",
+                        key
+                    ),
+                );
+            }
+        }
+        check(
+            self.log.redaction != RedactionPolicy::Hash
+                || !self.log.redaction_key.expose_secret().is_empty(),
+            "log.redaction_key must be set when log.redaction is hash, \
+            e.g. with APP_LOG__REDACTION_KEY.",
+        );
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(self.log.directives()) {
+            check(
+                false,
+                &format!("log.filter or log.modules is not a valid filter: {}", e),
+            );
+        }
+        if let Some(file) = &self.log.file {
+            check(
+                !file.directory.trim().is_empty(),
+                "log.file.directory must not be empty.",
+            );
+            check(
+                !file.file_name_prefix.trim().is_empty(),
+                "log.file.file_name_prefix must not be empty.",
+            );
+        }
+        if self.otlp.enabled {
+            check(
+                is_http_url(&self.otlp.endpoint),
+                "otlp.endpoint must be an absolute http(s) URL, e.g. `http://localhost:4317`.",
+            );
+            check(
+                self.otlp.timeout_milliseconds > 0,
+                "otlp.timeout_milliseconds must be greater than 0.",
+            );
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidConfiguration(problems))
         }
     }
 }
 
-impl TryFrom<String> for Environment {
-    type Error = String;
+fn is_http_url(s: &str) -> bool {
+    matches!(
+        reqwest::Url::parse(s),
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host()
+    )
+}
+
+/// A configuration profile, layered over `base.yaml` from
+/// `configuration/{name}.yaml`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Environment(String);
 
-    fn try_from(value: String) -> Result<Self, Self::Error> {
-        match value.to_lowercase().as_str() {
-            "local" => Ok(Self::Local),
-            "production" => Ok(Self::Production),
-            other => Err(format!(
-                "{} is not a supported environment. Use either `local` or `production`.",
-                other
-            )),
+impl Environment {
+    /// Resolves `name` to one of the profiles in `configuration_directory`.
+    pub fn resolve(name: &str, configuration_directory: &Path) -> Result<Self, String> {
+        let name = name.trim().to_lowercase();
+        let available = available_environments(configuration_directory);
+        if available.contains(&name) {
+            Ok(Self(name))
+        } else {
+            Err(format!(
+                "`{}` is not a known environment. Available environments: {}.",
+                name,
+                available.join(", ")
+            ))
         }
     }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether the secrets checked into `configuration/` may be used as is.
+    pub fn allows_default_secrets(&self) -> bool {
+        matches!(self.as_str(), "local" | "test")
+    }
+}
+
+/// The profile names in `configuration_directory`, i.e. its YAML files
+/// other than `base.yaml`, sorted.
+fn available_environments(configuration_directory: &Path) -> Vec<String> {
+    let mut environments: Vec<String> = std::fs::read_dir(configuration_directory)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "yaml" {
+                return None;
+            }
+            let name = path.file_stem()?.to_str()?.to_owned();
+            (name != "base").then_some(name)
+        })
+        .collect();
+    environments.sort();
+    environments
 }
 
+/// Loads the profile named by `APP_ENVIRONMENT`, `local` by default.
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
+    let environment = std::env::var("APP_ENVIRONMENT").unwrap_or_else(|_| "local".into());
+    get_configuration_for(&environment)
+}
+
+pub fn get_configuration_for(environment: &str) -> Result<Settings, config::ConfigError> {
     let base_path = std::env::current_dir().expect("Failed to determine the current directory");
-    let env: Environment = std::env::var("APP_ENVIRONMENT")
-        .unwrap_or_else(|_| "local".into())
-        .try_into()
-        .expect("Failed to parse APP_ENVIRONMENT.");
+    let configuration_directory = base_path.join("configuration");
+    let env = Environment::resolve(environment, &configuration_directory)
+        .map_err(config::ConfigError::Message)?;
 
-    let base_conf_path = base_path.join("configuration/base");
-    let env_conf_path = base_path.join(format!("configuration/{}", env.as_str()));
+    let base_conf_path = configuration_directory.join("base");
+    let env_conf_path = configuration_directory.join(env.as_str());
 
     let builder = Config::builder()
         .add_source(File::new(
             base_conf_path.to_str().unwrap(),
             FileFormat::Yaml,
         ))
-        .add_source(File::new(env_conf_path.to_str().unwrap(), FileFormat::Yaml));
+        .add_source(File::new(env_conf_path.to_str().unwrap(), FileFormat::Yaml))
+        .add_source(environment_source(std::env::vars())?);
+
+    let mut settings = builder.build()?.try_deserialize::<Settings>()?;
+    settings.environment = Some(env);
+    Ok(settings)
+}
+
+const ENV_PREFIX: &str = "APP_";
+const ENV_FILE_SUFFIX: &str = "_FILE";
+
+/// Overrides any key from the environment, e.g. `APP_DATABASE__PASSWORD` sets
+/// `database.password`. Appending `_FILE` reads the value from the file the
+/// variable points at instead, for secrets mounted by the orchestrator.
+fn environment_source(
+    vars: impl Iterator<Item = (String, String)>,
+) -> Result<config::Environment, config::ConfigError> {
+    let mut values = HashMap::new();
+    let mut files = Vec::new();
+    for (name, value) in vars.filter(|(name, _)| name.starts_with(ENV_PREFIX)) {
+        match name.strip_suffix(ENV_FILE_SUFFIX) {
+            Some(name) => files.push((name.to_owned(), value)),
+            None => {
+                values.insert(name, value);
+            }
+        }
+    }
+    for (name, path) in files {
+        if values.contains_key(&name) {
+            return Err(config::ConfigError::Message(format!(
+                "Both {} and {}{} are set, use only one of them.",
+                name, name, ENV_FILE_SUFFIX
+            )));
+        }
+        let value = std::fs::read_to_string(&path).map_err(|e| {
+            config::ConfigError::Message(format!(
+                "Failed to read {}{} from {}: {}",
+                name, ENV_FILE_SUFFIX, path, e
+            ))
+        })?;
+        values.insert(name, value.trim_end_matches(['\r', '\n']).to_owned());
+    }
+    Ok(config::Environment::with_prefix("APP")
+        .prefix_separator("_")
+        .separator("__")
+        .source(Some(values.into_iter().collect())))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::{
+        environment_source, get_configuration_for, EmailClientSettings, Environment, LogFormat,
+        LogSettings, RateBudget, RateLimitSettings, RedactionPolicy, SenderIdentitySettings,
+        Settings, SslMode,
+    };
+    use claim::{assert_err, assert_ok};
+    use config::{Config, File, FileFormat};
+    use secrecy::{ExposeSecret, Secret};
+    use sqlx::postgres::PgSslMode;
+    use std::collections::HashMap;
+
+    fn settings(identities: &[(&str, &str, Option<&str>)]) -> EmailClientSettings {
+        EmailClientSettings {
+            base_url: "http://localhost".into(),
+            sender_email: "sender@example.com".into(),
+            sender_name: Some("Zero To Production".into()),
+            sender_identities: identities
+                .iter()
+                .map(|(key, email, name)| {
+                    (
+                        key.to_string(),
+                        SenderIdentitySettings {
+                            email: email.to_string(),
+                            name: name.map(str::to_string),
+                        },
+                    )
+                })
+                .collect(),
+            authorization_token: Secret::new("token".into()),
+            timeout_milliseconds: 1000,
+            batch_enabled: true,
+            rate_limit: Default::default(),
+            circuit_breaker: Default::default(),
+        }
+    }
+
+    #[test]
+    fn well_formed_sender_identities_are_accepted() {
+        let settings = settings(&[
+            ("editorial", "editor@example.com", Some("The Editors")),
+            ("alerts", "alerts@example.com", None),
+        ]);
+
+        let identities = assert_ok!(settings.sender_identities());
+        assert_eq!(
+            r#""The Editors" <editor@example.com>"#,
+            identities["editorial"].to_string()
+        );
+        assert_eq!("alerts@example.com", identities["alerts"].to_string());
+    }
+
+    #[test]
+    fn every_malformed_sender_identity_is_reported() {
+        let settings = settings(&[
+            ("bad_email", "not-an-email", None),
+            ("bad_name", "editor@example.com", Some("Line\nbreak")),
+        ]);
+
+        let error = assert_err!(settings.sender_identities());
+        assert!(error.contains("'bad_email'"));
+        assert!(error.contains("'bad_name'"));
+    }
+
+    #[test]
+    fn a_malformed_default_sender_prevents_building_the_client() {
+        let mut settings = settings(&[]);
+        settings.sender_name = Some("<nope>".into());
+
+        assert!(settings.client().is_err());
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    fn layered(vars: impl Iterator<Item = (String, String)>) -> Config {
+        let yaml = "database:\n  password: from-file\n  port: 5432\n";
+        Config::builder()
+            .add_source(File::from_str(yaml, FileFormat::Yaml))
+            .add_source(environment_source(vars).unwrap())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn environment_variables_override_nested_keys() {
+        let config = layered(vars(&[
+            ("APP_DATABASE__PASSWORD", "from-env"),
+            ("APP_EMAIL_CLIENT__AUTHORIZATION_TOKEN", "token"),
+            ("DATABASE__PORT", "1234"),
+        ]));
+
+        assert_eq!("from-env", config.get_string("database.password").unwrap());
+        assert_eq!(
+            "token",
+            config
+                .get_string("email_client.authorization_token")
+                .unwrap()
+        );
+        assert_eq!(5432, config.get_int("database.port").unwrap());
+    }
+
+    #[test]
+    fn file_variables_are_read_from_disk() {
+        let path = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::write(&path, "s3cr3t\n").unwrap();
+
+        let config = layered(vars(&[(
+            "APP_DATABASE__PASSWORD_FILE",
+            path.to_str().unwrap(),
+        )]));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!("s3cr3t", config.get_string("database.password").unwrap());
+    }
+
+    fn typed(vars: impl Iterator<Item = (String, String)>) -> Settings {
+        Config::builder()
+            .add_source(File::new("configuration/base", FileFormat::Yaml))
+            .add_source(File::new("configuration/test", FileFormat::Yaml))
+            .add_source(environment_source(vars).unwrap())
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn secrets_that_look_like_numbers_are_kept_verbatim() {
+        let path = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::write(&path, "1e5\n").unwrap();
+
+        let settings = typed(vars(&[
+            ("APP_DATABASE__PASSWORD", "007"),
+            (
+                "APP_EMAIL_CLIENT__AUTHORIZATION_TOKEN_FILE",
+                path.to_str().unwrap(),
+            ),
+            ("APP_DATABASE__PORT", "6543"),
+            ("APP_TRACKING__ENABLED", "false"),
+        ]));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!("007", settings.database.password.expose_secret());
+        assert_eq!(
+            "1e5",
+            settings.email_client.authorization_token.expose_secret()
+        );
+        assert_eq!(6543, settings.database.port);
+        assert!(!settings.tracking.enabled);
+    }
+
+    #[test]
+    fn a_missing_secret_file_is_an_error() {
+        let vars = vars(&[("APP_DATABASE__PASSWORD_FILE", "/does/not/exist")]);
+
+        assert_err!(environment_source(vars));
+    }
+
+    #[test]
+    fn setting_a_key_and_its_file_variant_is_an_error() {
+        let vars = vars(&[
+            ("APP_DATABASE__PASSWORD", "from-env"),
+            ("APP_DATABASE__PASSWORD_FILE", "/run/secrets/password"),
+        ]);
+
+        assert_err!(environment_source(vars));
+    }
+
+    #[test]
+    fn debug_output_does_not_contain_secrets() {
+        let mut settings = get_configuration_for("test").expect("Failed to read configuration.");
+        settings.database.password = Secret::new("db-password-canary".into());
+        settings.email_client.authorization_token = Secret::new("postmark-token-canary".into());
+        settings.tracking.signing_key = Secret::new("signing-key-canary".into());
+        settings.log.redaction_key = Secret::new("redaction-key-canary".into());
+
+        let debug = format!("{:?}", settings);
+
+        assert!(!debug.contains("canary"), "Secrets leaked: {}", debug);
+        assert!(debug.contains("REDACTED"));
+    }
+
+    #[test]
+    fn the_default_configuration_is_valid() {
+        let settings = get_configuration_for("test").unwrap();
+
+        assert_ok!(settings.validate());
+    }
+
+    #[test]
+    fn every_configuration_problem_is_reported() {
+        let mut settings = get_configuration_for("test").unwrap();
+        settings.application.base_url = "127.0.0.1".into();
+        settings.database.password = Secret::new(String::new());
+        settings.email_client.base_url = "localhost".into();
+        settings.email_client.sender_email = "not-an-email".into();
+        settings.email_client.timeout_milliseconds = 0;
+        settings.email_outbox.max_attempts = 0;
+
+        let problems = assert_err!(settings.validate()).0;
+
+        assert_eq!(6, problems.len(), "{:#?}", problems);
+        for key in [
+            "application.base_url",
+            "database.password",
+            "email_client.base_url",
+            "email_client.sender_email",
+            "email_client.timeout_milliseconds",
+            "email_outbox.max_attempts",
+        ] {
+            assert!(
+                problems.iter().any(|problem| problem.starts_with(key)),
+                "No problem reported for {}",
+                key
+            );
+        }
+    }
+
+    /// A deployed profile, with only what the platform always sets.
+    fn deployed_configuration(environment: &str) -> Settings {
+        Config::builder()
+            .add_source(File::new("configuration/base", FileFormat::Yaml))
+            .add_source(File::new(
+                &format!("configuration/{}", environment),
+                FileFormat::Yaml,
+            ))
+            .set_override("application.base_url", "https://example.com")
+            .unwrap()
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn deployed_profiles_have_to_provide_a_tracking_signing_key() {
+        for environment in ["production", "staging"] {
+            let settings = deployed_configuration(environment);
+
+            let problems = assert_err!(settings.validate()).0;
+
+            assert!(
+                problems
+                    .iter()
+                    .any(|problem| problem.starts_with("tracking.signing_key")),
+                "{:#?}",
+                problems
+            );
+        }
+    }
+
+    #[test]
+    fn development_secrets_are_rejected_outside_local_and_test() {
+        let mut settings = deployed_configuration("production");
+        settings.tracking.signing_key = Secret::new("my-secret-tracking-key".into());
+        settings.log.redaction_key = Secret::new("my-secret-redaction-key".into());
+
+        let problems = assert_err!(settings.validate()).0;
+
+        assert_eq!(4, problems.len(), "{:#?}", problems);
+        for key in [
+            "database.password",
+            "email_client.authorization_token",
+            "tracking.signing_key",
+            "log.redaction_key",
+        ] {
+            assert!(
+                problems.iter().any(|problem| problem.starts_with(key)),
+                "No problem reported for {}",
+                key
+            );
+        }
+
+        settings.database.password = Secret::new("s3cr3t".into());
+        settings.email_client.authorization_token = Secret::new("postmark-token".into());
+        settings.tracking.signing_key = Secret::new("signing-key".into());
+        settings.log.redaction_key = Secret::new("redaction-key".into());
+        assert_ok!(settings.validate());
+    }
+
+    #[test]
+    fn zero_rate_limit_budgets_are_rejected() {
+        let mut settings = get_configuration_for("test").unwrap();
+        settings.email_client.rate_limit = RateLimitSettings {
+            per_second: None,
+            per_hour: Some(0),
+            per_domain: HashMap::from([(
+                "gmail.com".to_owned(),
+                RateBudget {
+                    per_second: Some(0),
+                    per_hour: Some(10),
+                },
+            )]),
+        };
+
+        let problems = assert_err!(settings.validate()).0;
+
+        assert_eq!(
+            vec![
+                "email_client.rate_limit.per_hour must be at least 1, \
+                leave it unset for no limit.",
+                "email_client.rate_limit.per_domain.gmail.com.per_second must be at least 1, \
+                leave it unset for no limit.",
+            ],
+            problems
+        );
+    }
+
+    #[test]
+    fn hashing_identifiers_requires_a_redaction_key() {
+        let mut settings = get_configuration_for("test").unwrap();
+        settings.log.redaction_key = Secret::new(String::new());
+
+        let problems = assert_err!(settings.validate()).0;
+        assert!(
+            problems[0].starts_with("log.redaction_key"),
+            "{:#?}",
+            problems
+        );
+
+        settings.log.redaction = RedactionPolicy::Mask;
+        assert_ok!(settings.validate());
+    }
+
+    #[test]
+    fn the_otlp_endpoint_is_only_checked_when_exporting() {
+        let mut settings = get_configuration_for("test").unwrap();
+        settings.otlp.endpoint = "localhost:4317".into();
+        assert_ok!(settings.validate());
+
+        settings.otlp.enabled = true;
+        let problems = assert_err!(settings.validate()).0;
+        assert!(problems[0].starts_with("otlp.endpoint"), "{:#?}", problems);
+    }
+
+    #[test]
+    fn module_levels_are_appended_to_the_log_filter() {
+        let config = layered(vars(&[
+            ("APP_LOG__FILTER", "info"),
+            ("APP_LOG__FORMAT", "logfmt"),
+            ("APP_LOG__MODULES__SQLX", "warn"),
+            ("APP_LOG__MODULES__ACTIX_SERVER", "error"),
+        ]));
+        let settings: LogSettings = config.get("log").unwrap();
+
+        assert_eq!(LogFormat::Logfmt, settings.format);
+        assert_eq!("info,actix_server=error,sqlx=warn", settings.directives());
+    }
+
+    #[test]
+    fn an_invalid_module_level_is_reported() {
+        let mut settings = get_configuration_for("test").unwrap();
+        settings.log.modules.insert("sqlx".into(), "loud".into());
+
+        let problems = assert_err!(settings.validate()).0;
+
+        assert!(problems[0].starts_with("log.filter"), "{:#?}", problems);
+    }
+
+    #[test]
+    fn the_ssl_mode_defaults_to_prefer_unless_ssl_is_required() {
+        let mut settings = get_configuration_for("test").unwrap().database;
+        settings.require_ssl = false;
+        settings.ssl_mode = None;
+        assert!(matches!(settings.ssl_mode(), PgSslMode::Prefer));
+
+        settings.require_ssl = true;
+        assert!(matches!(settings.ssl_mode(), PgSslMode::Require));
+
+        settings.ssl_mode = Some(SslMode::VerifyFull);
+        assert!(matches!(settings.ssl_mode(), PgSslMode::VerifyFull));
+    }
+
+    #[test]
+    fn ssl_modes_are_read_in_kebab_case() {
+        let config = layered(vars(&[("APP_DATABASE__SSL_MODE", "verify-ca")]));
+
+        assert_eq!(
+            SslMode::VerifyCa,
+            config.get::<SslMode>("database.ssl_mode").unwrap()
+        );
+    }
+
+    #[test]
+    fn every_profile_in_the_configuration_directory_is_an_environment() {
+        let directory = std::env::current_dir().unwrap().join("configuration");
+        for name in ["local", "production", "staging", "test", "Staging"] {
+            assert_ok!(Environment::resolve(name, &directory));
+        }
+    }
+
+    #[test]
+    fn an_unknown_environment_lists_the_available_ones() {
+        let directory = std::env::current_dir().unwrap().join("configuration");
+
+        let error = assert_err!(Environment::resolve("qa", &directory));
 
-    match builder.build() {
-        Ok(config) => config.try_deserialize::<Settings>(),
-        Err(e) => Err(e),
+        assert!(error.contains("`qa`"));
+        assert!(error.contains("local, production, staging, test"));
+        assert!(!error.contains("base"));
     }
 }
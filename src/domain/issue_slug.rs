@@ -0,0 +1,59 @@
+use uuid::Uuid;
+
+#[derive(Debug)]
+pub struct IssueSlug(String);
+
+impl IssueSlug {
+    /// Builds a URL-friendly slug from the issue title, suffixed with part of
+    /// the issue id so that issues sharing a title get distinct slugs.
+    pub fn new(title: &str, newsletter_issue_id: Uuid) -> IssueSlug {
+        let mut slug = String::with_capacity(title.len());
+        for c in title.chars().flat_map(char::to_lowercase) {
+            if c.is_alphanumeric() {
+                slug.push(c);
+            } else if !slug.is_empty() && !slug.ends_with('-') {
+                slug.push('-');
+            }
+        }
+        let id = newsletter_issue_id.simple().to_string();
+        if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+        slug.push_str(&id[..8]);
+        Self(slug)
+    }
+}
+
+impl AsRef<str> for IssueSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::IssueSlug;
+    use uuid::Uuid;
+
+    fn id() -> Uuid {
+        Uuid::parse_str("1a2b3c4d-0000-0000-0000-000000000000").unwrap()
+    }
+
+    #[test]
+    fn titles_are_lowercased_and_hyphenated() {
+        let slug = IssueSlug::new("Hello, World!", id());
+        assert_eq!(slug.as_ref(), "hello-world-1a2b3c4d");
+    }
+
+    #[test]
+    fn leading_and_repeated_separators_are_collapsed() {
+        let slug = IssueSlug::new("  Rust -- & Postgres  ", id());
+        assert_eq!(slug.as_ref(), "rust-postgres-1a2b3c4d");
+    }
+
+    #[test]
+    fn a_title_without_alphanumeric_characters_falls_back_to_the_id() {
+        let slug = IssueSlug::new("!!!", id());
+        assert_eq!(slug.as_ref(), "1a2b3c4d");
+    }
+}
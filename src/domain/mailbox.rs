@@ -0,0 +1,62 @@
+use super::{SubscriberEmail, SubscriberName};
+use std::fmt::{Display, Formatter};
+
+/// An email address with an optional display name, rendered as
+/// `"Name" <address>` in the `From` and `To` headers.
+#[derive(Debug, Clone)]
+pub struct Mailbox {
+    email: SubscriberEmail,
+    name: Option<SubscriberName>,
+}
+
+impl Mailbox {
+    pub fn new(email: SubscriberEmail, name: Option<SubscriberName>) -> Self {
+        Self { email, name }
+    }
+
+    pub fn email(&self) -> &SubscriberEmail {
+        &self.email
+    }
+
+    pub fn name(&self) -> Option<&SubscriberName> {
+        self.name.as_ref()
+    }
+}
+
+impl From<SubscriberEmail> for Mailbox {
+    fn from(email: SubscriberEmail) -> Self {
+        Self::new(email, None)
+    }
+}
+
+impl Display for Mailbox {
+    // `SubscriberName` rejects quotes, backslashes and control characters,
+    // so the name can be quoted as-is.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, r#""{}" <{}>"#, name.as_ref(), self.email.as_ref()),
+            None => write!(f, "{}", self.email.as_ref()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::{Mailbox, SubscriberEmail, SubscriberName};
+
+    #[test]
+    fn a_mailbox_without_a_name_renders_the_bare_address() {
+        let email = SubscriberEmail::parse("ursula@domain.com".into()).unwrap();
+        assert_eq!("ursula@domain.com", Mailbox::from(email).to_string());
+    }
+
+    #[test]
+    fn a_mailbox_with_a_name_renders_the_quoted_name_and_address() {
+        let email = SubscriberEmail::parse("ursula@domain.com".into()).unwrap();
+        let name = SubscriberName::parse("Ursula Le Guin".into()).unwrap();
+        assert_eq!(
+            r#""Ursula Le Guin" <ursula@domain.com>"#,
+            Mailbox::new(email, Some(name)).to_string()
+        );
+    }
+}
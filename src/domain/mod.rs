@@ -1,7 +1,11 @@
+mod issue_slug;
+mod mailbox;
 mod new_subscriber;
 mod subscriber_email;
 mod subscriber_name;
 
+pub use issue_slug::IssueSlug;
+pub use mailbox::Mailbox;
 pub use new_subscriber::NewSubscriber;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
@@ -1,6 +1,6 @@
 use unicode_segmentation::UnicodeSegmentation;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SubscriberName(String);
 
 impl SubscriberName {
@@ -8,9 +8,19 @@ impl SubscriberName {
         let is_empty_or_whitespace = s.trim().is_empty();
         let is_too_long = s.graphemes(true).count() > 256;
         let forbidden_characters = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
-        let contains_forbidden_characters = s.chars().any(|c| forbidden_characters.contains(&c));
-        if is_empty_or_whitespace || is_too_long || contains_forbidden_characters {
-            Err(format!("{} is not a valid subscriber name.", s))
+        let contains_forbidden_characters = s
+            .chars()
+            .any(|c| forbidden_characters.contains(&c) || c.is_control());
+        // The name is left out: the message is shown to the client and logged.
+        if is_empty_or_whitespace {
+            Err("The subscriber name is empty.".to_string())
+        } else if is_too_long {
+            Err("The subscriber name is longer than 256 characters.".to_string())
+        } else if contains_forbidden_characters {
+            Err(format!(
+                "The subscriber name contains a control character or one of {}.",
+                forbidden_characters.iter().collect::<String>()
+            ))
         } else {
             Ok(Self(s))
         }
@@ -57,6 +67,16 @@ mod tests {
         }
     }
     #[test]
+    fn names_containing_control_characters_are_rejected() {
+        for name in [
+            "Ursula\nLe Guin",
+            "Ursula\r\nBcc: someone",
+            "Ursula\tLe Guin",
+        ] {
+            assert_err!(SubscriberName::parse(name.to_string()));
+        }
+    }
+    #[test]
     fn a_valid_name_is_parsed_successfully() {
         let name = "Ursula Le Guin".to_string();
         assert_ok!(SubscriberName::parse(name));
@@ -1,75 +1,811 @@
-use crate::domain::SubscriberEmail;
-use reqwest::Client;
+use crate::{
+    circuit_breaker::{CircuitBreaker, CircuitState},
+    configuration::{CircuitBreakerSettings, RateLimitSettings},
+    domain::{Mailbox, SubscriberEmail},
+    metrics::Metrics,
+    rate_limiter::RateLimiter,
+    telemetry::trace_context_headers,
+};
+use reqwest::{Client, StatusCode};
+use secrecy::{ExposeSecret, Secret};
+use serde::ser::SerializeStruct;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
+/// Postmark accepts at most 500 messages per call to `/email/batch`.
+pub const MAX_BATCH_SIZE: usize = 500;
+/// Postmark refuses calls to `/email/batch` with a larger body.
+const MAX_BATCH_PAYLOAD_SIZE: usize = 50 * 1000 * 1000;
+/// Postmark limits on message headers and attachments.
+const MAX_TAG_LENGTH: usize = 1000;
+const MAX_METADATA_FIELDS: usize = 10;
+const MAX_METADATA_KEY_LENGTH: usize = 20;
+const MAX_METADATA_VALUE_LENGTH: usize = 80;
+pub const MAX_ATTACHMENTS_SIZE: usize = 10 * 1024 * 1024;
+/// The longest a request waits for the send budget. Per-second budgets
+/// refill within it, while an exhausted hourly budget fails the request
+/// instead of holding it for up to an hour.
+const MAX_SEND_BUDGET_WAIT: Duration = Duration::from_secs(1);
+
 pub struct EmailClient {
     http_client: Client,
     base_url: String,
-    sender: SubscriberEmail,
-    authorization_token: String,
+    sender: Mailbox,
+    sender_identities: HashMap<String, Mailbox>,
+    authorization_token: Secret<String>,
+    batch_enabled: AtomicBool,
+    rate_limiter: RateLimiter,
+    circuit_breaker: CircuitBreaker,
+    metrics: Arc<Metrics>,
+}
+
+/// Postmark's error code for a recipient that bounced, complained or unsubscribed.
+const INACTIVE_RECIPIENT_ERROR_CODE: i64 = 406;
+/// Postmark's error code for a malformed request, which includes invalid addresses.
+const INVALID_EMAIL_REQUEST_ERROR_CODE: i64 = 300;
+
+#[derive(Debug)]
+pub enum EmailClientError {
+    /// The circuit breaker is open: the request was not attempted.
+    CircuitOpen,
+    /// Our own send budget is spent: the request was not attempted.
+    BudgetExhausted {
+        retry_after: Duration,
+    },
+    Timeout(reqwest::Error),
+    Connection(reqwest::Error),
+    /// The provider throttled us, optionally telling us when to come back.
+    RateLimited {
+        retry_after: Option<Duration>,
+    },
+    /// The provider will never deliver to this recipient.
+    InvalidRecipient {
+        error_code: i64,
+        message: ProviderMessage,
+    },
+    Rejected {
+        status: StatusCode,
+        error_code: i64,
+        message: ProviderMessage,
+    },
+    Unexpected(reqwest::Error),
+}
+
+/// The explanation that comes with a provider error code. It often quotes
+/// the recipient's address, so neither `Debug` nor `EmailClientError`'s
+/// `Display` show it: the code is enough to look the error up.
+pub struct ProviderMessage(String);
+
+impl ProviderMessage {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for ProviderMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+impl EmailClientError {
+    /// Whether the same request may succeed if it is tried again later.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            EmailClientError::CircuitOpen
+            | EmailClientError::BudgetExhausted { .. }
+            | EmailClientError::Timeout(_)
+            | EmailClientError::Connection(_)
+            | EmailClientError::RateLimited { .. } => true,
+            EmailClientError::Rejected { status, .. } => status.is_server_error(),
+            EmailClientError::InvalidRecipient { .. } | EmailClientError::Unexpected(_) => false,
+        }
+    }
+
+    /// Rejections (4xx) mean the provider is up and refused our request, and
+    /// throttling (429) that it is up and wants us to slow down, so only
+    /// timeouts, connection failures and 5xx trip the circuit.
+    fn is_provider_failure(&self) -> bool {
+        match self {
+            EmailClientError::Rejected { status, .. } => status.is_server_error(),
+            EmailClientError::InvalidRecipient { .. }
+            | EmailClientError::RateLimited { .. }
+            | EmailClientError::CircuitOpen
+            | EmailClientError::BudgetExhausted { .. } => false,
+            EmailClientError::Timeout(_)
+            | EmailClientError::Connection(_)
+            | EmailClientError::Unexpected(_) => true,
+        }
+    }
+
+    /// The `outcome` label of the emails that failed with this error.
+    fn outcome(&self) -> &'static str {
+        match self {
+            EmailClientError::CircuitOpen => "circuit_open",
+            EmailClientError::BudgetExhausted { .. } => "budget_exhausted",
+            EmailClientError::Timeout(_) => "timeout",
+            EmailClientError::Connection(_) => "connection_failed",
+            EmailClientError::RateLimited { .. } => "rate_limited",
+            EmailClientError::InvalidRecipient { .. } => "invalid_recipient",
+            EmailClientError::Rejected { .. } => "rejected",
+            EmailClientError::Unexpected(_) => "unexpected",
+        }
+    }
+
+    fn rejection(status: StatusCode, error_code: i64, message: String) -> Self {
+        let invalid_recipient = is_invalid_recipient(error_code, &message);
+        let message = ProviderMessage(message);
+        if invalid_recipient {
+            EmailClientError::InvalidRecipient {
+                error_code,
+                message,
+            }
+        } else {
+            EmailClientError::Rejected {
+                status,
+                error_code,
+                message,
+            }
+        }
+    }
+}
+
+/// Postmark names the field it could not parse, e.g. `Invalid 'To' address`.
+/// Any other malformed request is our mistake rather than the recipient's.
+fn is_invalid_recipient(error_code: i64, message: &str) -> bool {
+    error_code == INACTIVE_RECIPIENT_ERROR_CODE
+        || (error_code == INVALID_EMAIL_REQUEST_ERROR_CODE && message.contains("'To'"))
+}
+
+impl std::fmt::Display for EmailClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EmailClientError::CircuitOpen => {
+                write!(f, "The email provider is unavailable, the circuit is open.")
+            }
+            EmailClientError::BudgetExhausted { .. } => {
+                write!(f, "The email send budget is exhausted.")
+            }
+            EmailClientError::Timeout(_) => {
+                write!(f, "The request to the email provider timed out.")
+            }
+            EmailClientError::Connection(_) => {
+                write!(f, "Failed to connect to the email provider.")
+            }
+            EmailClientError::RateLimited { .. } => {
+                write!(f, "The email provider is rate limiting our requests.")
+            }
+            EmailClientError::InvalidRecipient { error_code, .. } => write!(
+                f,
+                "The email provider refused the recipient. Error code {}.",
+                error_code
+            ),
+            EmailClientError::Rejected {
+                status, error_code, ..
+            } => write!(
+                f,
+                "The email provider rejected the request with {}. Error code {}.",
+                status, error_code
+            ),
+            EmailClientError::Unexpected(_) => {
+                write!(f, "The request to the email provider failed.")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EmailClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EmailClientError::Timeout(e)
+            | EmailClientError::Connection(e)
+            | EmailClientError::Unexpected(e) => Some(e),
+            EmailClientError::CircuitOpen
+            | EmailClientError::BudgetExhausted { .. }
+            | EmailClientError::RateLimited { .. }
+            | EmailClientError::InvalidRecipient { .. }
+            | EmailClientError::Rejected { .. } => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for EmailClientError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Self::Timeout(e)
+        } else if e.is_connect() {
+            Self::Connection(e)
+        } else {
+            Self::Unexpected(e)
+        }
+    }
+}
+
+/// The body Postmark returns alongside a non-2xx status.
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct ErrorResponse {
+    error_code: i64,
+    message: String,
+}
+
+/// A file attached to an email. Inline attachments carry a content id and
+/// are referenced from the HTML body as `<img src="cid:{content_id}">`.
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub name: String,
+    pub content_type: String,
+    pub content: Vec<u8>,
+    pub content_id: Option<String>,
+}
+
+impl Attachment {
+    pub fn new(name: String, content_type: String, content: Vec<u8>) -> Self {
+        Self {
+            name,
+            content_type,
+            content,
+            content_id: None,
+        }
+    }
+
+    pub fn inline(
+        name: String,
+        content_type: String,
+        content: Vec<u8>,
+        content_id: String,
+    ) -> Self {
+        Self {
+            content_id: Some(content_id),
+            ..Self::new(name, content_type, content)
+        }
+    }
+}
+
+impl Attachment {
+    /// The same attachment without its content, to measure everything else
+    /// it adds to a request body.
+    fn without_content(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            content_type: self.content_type.clone(),
+            content: Vec::new(),
+            content_id: self.content_id.clone(),
+        }
+    }
+
+    /// The length of the content once it is base64-encoded.
+    fn encoded_size(&self) -> usize {
+        self.content.len().div_ceil(3) * 4
+    }
+}
+
+impl serde::Serialize for Attachment {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut attachment = serializer.serialize_struct("Attachment", 4)?;
+        attachment.serialize_field("Name", &self.name)?;
+        attachment.serialize_field("Content", &base64::encode(&self.content))?;
+        attachment.serialize_field("ContentType", &self.content_type)?;
+        match &self.content_id {
+            Some(content_id) => {
+                attachment.serialize_field("ContentID", &format!("cid:{}", content_id))?
+            }
+            None => attachment.skip_field("ContentID")?,
+        }
+        attachment.end()
+    }
+}
+
+/// Optional headers and attachments shared by every message of a send.
+#[derive(Debug, Default)]
+pub struct EmailOptions {
+    /// Overrides the client's default sender.
+    pub sender: Option<Mailbox>,
+    pub reply_to: Option<SubscriberEmail>,
+    pub cc: Vec<SubscriberEmail>,
+    pub bcc: Vec<SubscriberEmail>,
+    pub tag: Option<String>,
+    pub metadata: HashMap<String, String>,
+    pub attachments: Vec<Attachment>,
+}
+
+impl EmailOptions {
+    /// Checks the options against the limits enforced by the provider, so a
+    /// send is refused up front instead of being rejected message by message.
+    pub fn validate(&self) -> Result<(), String> {
+        if matches!(&self.tag, Some(tag) if tag.chars().count() > MAX_TAG_LENGTH) {
+            return Err(format!(
+                "The tag must be at most {} characters long.",
+                MAX_TAG_LENGTH
+            ));
+        }
+        if self.metadata.len() > MAX_METADATA_FIELDS {
+            return Err(format!(
+                "At most {} metadata fields are allowed.",
+                MAX_METADATA_FIELDS
+            ));
+        }
+        for (key, value) in &self.metadata {
+            if key.is_empty() || key.chars().count() > MAX_METADATA_KEY_LENGTH {
+                return Err(format!(
+                    "Metadata keys must be between 1 and {} characters long.",
+                    MAX_METADATA_KEY_LENGTH
+                ));
+            }
+            if value.chars().count() > MAX_METADATA_VALUE_LENGTH {
+                return Err(format!(
+                    "Metadata values must be at most {} characters long.",
+                    MAX_METADATA_VALUE_LENGTH
+                ));
+            }
+        }
+        let attachments_size: usize = self.attachments.iter().map(|a| a.content.len()).sum();
+        if attachments_size > MAX_ATTACHMENTS_SIZE {
+            return Err(format!(
+                "Attachments must not exceed {} bytes in total.",
+                MAX_ATTACHMENTS_SIZE
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[derive(serde::Serialize)]
 #[serde(rename_all = "PascalCase")]
 struct SendEmailRequest<'a> {
     from: &'a str,
-    to: &'a str,
+    to: String,
     subject: &'a str,
     html_body: &'a str,
     text_body: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<&'a str>,
+    #[serde(
+        skip_serializing_if = "<[_]>::is_empty",
+        serialize_with = "serialize_addresses"
+    )]
+    cc: &'a [SubscriberEmail],
+    #[serde(
+        skip_serializing_if = "<[_]>::is_empty",
+        serialize_with = "serialize_addresses"
+    )]
+    bcc: &'a [SubscriberEmail],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<&'a str>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    metadata: &'a HashMap<String, String>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    attachments: &'a [Attachment],
+}
+
+impl<'a> SendEmailRequest<'a> {
+    fn new(
+        from: &'a str,
+        to: &Mailbox,
+        subject: &'a str,
+        html_body: &'a str,
+        text_body: &'a str,
+        options: &'a EmailOptions,
+    ) -> Self {
+        Self {
+            from,
+            to: to.to_string(),
+            subject,
+            html_body,
+            text_body,
+            reply_to: options.reply_to.as_ref().map(AsRef::as_ref),
+            cc: &options.cc,
+            bcc: &options.bcc,
+            tag: options.tag.as_deref(),
+            metadata: &options.metadata,
+            attachments: &options.attachments,
+        }
+    }
+
+    fn batched(from: &'a str, email: &'a BatchEmail, options: &'a EmailOptions) -> Self {
+        Self::new(
+            from,
+            &email.recipient,
+            &email.subject,
+            &email.html_content,
+            &email.text_content,
+            options,
+        )
+    }
+
+    /// The size of the request body, without base64-encoding the attachments
+    /// to find out.
+    fn payload_size(self) -> usize {
+        let attachments = self.attachments;
+        let stubs: Vec<Attachment> = attachments
+            .iter()
+            .map(Attachment::without_content)
+            .collect();
+        let encoded_size: usize = attachments.iter().map(Attachment::encoded_size).sum();
+        let request = SendEmailRequest {
+            attachments: &stubs,
+            ..self
+        };
+        serde_json::to_vec(&request).map_or(0, |json| json.len()) + encoded_size
+    }
+}
+
+/// Splits messages of the given sizes into consecutive batches that stay
+/// within `MAX_BATCH_SIZE` and `MAX_BATCH_PAYLOAD_SIZE`. A message over the
+/// payload limit on its own still gets a batch, for the provider to refuse.
+/// A batch is a JSON array, so it also counts the brackets and the commas.
+fn batch_ranges(sizes: &[usize]) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    let mut payload_size = 1;
+    for (i, size) in sizes.iter().enumerate() {
+        let full = i - start == MAX_BATCH_SIZE || payload_size + size + 1 > MAX_BATCH_PAYLOAD_SIZE;
+        if full && i > start {
+            ranges.push(start..i);
+            start = i;
+            payload_size = 1;
+        }
+        payload_size += size + 1;
+    }
+    if start < sizes.len() {
+        ranges.push(start..sizes.len());
+    }
+    ranges
+}
+
+/// Postmark takes multiple recipients as a single comma-separated string.
+fn serialize_addresses<S: serde::Serializer>(
+    addresses: &&[SubscriberEmail],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let addresses: Vec<&str> = addresses.iter().map(AsRef::as_ref).collect();
+    serializer.serialize_str(&addresses.join(","))
+}
+
+pub struct BatchEmail {
+    pub recipient: Mailbox,
+    pub subject: String,
+    pub html_content: String,
+    pub text_content: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct BatchEmailResponse {
+    error_code: i64,
+    message: String,
+}
+
+/// The provider's verdict on a single message of a batch.
+#[derive(Debug)]
+pub struct BatchEmailResult {
+    pub recipient: String,
+    /// Shared by every message of a request that failed as a whole.
+    pub outcome: Result<(), Arc<EmailClientError>>,
+}
+
+impl BatchEmailResult {
+    pub fn is_success(&self) -> bool {
+        self.outcome.is_ok()
+    }
+
+    /// Postmark answers 200 to a batch and reports refused messages in the
+    /// body, with the error codes it would give a single message with 422.
+    fn from_response(recipient: String, response: BatchEmailResponse) -> Self {
+        let outcome = if response.error_code == 0 {
+            Ok(())
+        } else {
+            Err(Arc::new(EmailClientError::rejection(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                response.error_code,
+                response.message,
+            )))
+        };
+        Self { recipient, outcome }
+    }
+
+    fn outcome(&self) -> &'static str {
+        match &self.outcome {
+            Ok(()) => "sent",
+            Err(e) => e.outcome(),
+        }
+    }
 }
 
 impl EmailClient {
     pub fn new(
         base_url: String,
-        sender: SubscriberEmail,
-        authorization_token: String,
+        sender: Mailbox,
+        authorization_token: Secret<String>,
         timeout: Duration,
+        batch_enabled: bool,
+        rate_limit: &RateLimitSettings,
+        circuit_breaker: &CircuitBreakerSettings,
     ) -> Self {
         let http_client = Client::builder().timeout(timeout).build().unwrap();
         Self {
             http_client,
             base_url,
             sender,
+            sender_identities: HashMap::new(),
             authorization_token,
+            batch_enabled: AtomicBool::new(batch_enabled),
+            rate_limiter: RateLimiter::new(rate_limit),
+            circuit_breaker: CircuitBreaker::new(circuit_breaker),
+            metrics: Arc::new(Metrics::new()),
         }
     }
 
+    /// Reports send outcomes to `metrics` instead of a private registry.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn with_sender_identities(mut self, sender_identities: HashMap<String, Mailbox>) -> Self {
+        self.sender_identities = sender_identities;
+        self
+    }
+
+    /// Looks up one of the alternative senders configured by key.
+    pub fn sender_identity(&self, key: &str) -> Option<&Mailbox> {
+        self.sender_identities.get(key)
+    }
+
+    pub fn batch_enabled(&self) -> bool {
+        self.batch_enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_batch_enabled(&self, batch_enabled: bool) {
+        self.batch_enabled.store(batch_enabled, Ordering::Relaxed);
+    }
+
+    /// Swaps the send budgets of a running client.
+    pub async fn set_rate_limit(&self, rate_limit: &RateLimitSettings) {
+        self.rate_limiter.reconfigure(rate_limit).await;
+    }
+
+    pub fn circuit_state(&self) -> CircuitState {
+        self.circuit_breaker.state()
+    }
+
+    /// How long until the circuit lets a request through to the provider.
+    pub fn circuit_retry_after(&self) -> Duration {
+        self.circuit_breaker.retry_after()
+    }
+
+    /// Checks that the provider can be reached. Any HTTP response will do,
+    /// the request is not authenticated.
+    pub async fn ping(&self) -> Result<(), EmailClientError> {
+        self.http_client.get(&self.base_url).send().await?;
+        Ok(())
+    }
+
     pub async fn send_email(
         &self,
-        recipient: SubscriberEmail,
+        recipient: &Mailbox,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), EmailClientError> {
+        self.send_email_with_options(
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            &EmailOptions::default(),
+        )
+        .await
+    }
+
+    pub async fn send_email_with_options(
+        &self,
+        recipient: &Mailbox,
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), reqwest::Error> {
+        options: &EmailOptions,
+    ) -> Result<(), EmailClientError> {
         let url = format!("{}/email", self.base_url);
-        let request_body = SendEmailRequest {
-            from: self.sender.as_ref(),
-            to: recipient.as_ref(),
-            subject: subject,
-            html_body: html_content,
-            text_body: text_content,
-        };
-        let _builder = self
+        let sender = self.sender(options);
+        let request_body = SendEmailRequest::new(
+            &sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            options,
+        );
+        let outcome = self
+            .post(&url, &request_body, [recipient.email().as_ref()])
+            .await;
+        match &outcome {
+            Ok(_) => self.metrics.record_emails("sent", 1),
+            Err(e) => self.metrics.record_emails(e.outcome(), 1),
+        }
+        outcome?;
+
+        Ok(())
+    }
+
+    /// Sends `emails` through the batch endpoint, applying `options` to every
+    /// message. Messages are split across requests to stay within Postmark's
+    /// limits of 500 messages and 50 MB per request. The returned results
+    /// follow the order of `emails`: a request that failed as a whole fails
+    /// each of its messages, without affecting the other requests.
+    pub async fn send_batch(
+        &self,
+        emails: &[BatchEmail],
+        options: &EmailOptions,
+    ) -> Vec<BatchEmailResult> {
+        let url = format!("{}/email/batch", self.base_url);
+        let sender = self.sender(options);
+        let sizes: Vec<usize> = emails
+            .iter()
+            .map(|email| SendEmailRequest::batched(&sender, email, options).payload_size())
+            .collect();
+        let mut results = Vec::with_capacity(emails.len());
+        for range in batch_ranges(&sizes) {
+            let chunk = &emails[range];
+            let request_body: Vec<_> = chunk
+                .iter()
+                .map(|email| SendEmailRequest::batched(&sender, email, options))
+                .collect();
+            let recipients = chunk.iter().map(|email| email.recipient.email().as_ref());
+            let responses = match self.post(&url, &request_body, recipients).await {
+                Ok(response) => response
+                    .json::<Vec<BatchEmailResponse>>()
+                    .await
+                    .map_err(EmailClientError::from),
+                Err(e) => Err(e),
+            };
+            let responses = match responses {
+                Ok(responses) => responses,
+                Err(e) => {
+                    tracing::warn!("Failed to send a batch of {} messages. {}", chunk.len(), e);
+                    self.metrics.record_emails(e.outcome(), chunk.len() as u64);
+                    let e = Arc::new(e);
+                    results.extend(chunk.iter().map(|email| BatchEmailResult {
+                        recipient: email.recipient.email().as_ref().to_owned(),
+                        outcome: Err(e.clone()),
+                    }));
+                    continue;
+                }
+            };
+            if responses.len() != chunk.len() {
+                tracing::warn!(
+                    "The email provider returned {} results for a batch of {} messages.",
+                    responses.len(),
+                    chunk.len()
+                );
+            }
+            let mut responses = responses.into_iter();
+            for email in chunk {
+                let recipient = email.recipient.email().as_ref().to_owned();
+                let result = match responses.next() {
+                    Some(response) => BatchEmailResult::from_response(recipient, response),
+                    None => BatchEmailResult {
+                        recipient,
+                        outcome: Err(Arc::new(EmailClientError::rejection(
+                            StatusCode::OK,
+                            -1,
+                            "The email provider did not report a result.".to_owned(),
+                        ))),
+                    },
+                };
+                self.metrics.record_emails(result.outcome(), 1);
+                results.push(result);
+            }
+        }
+        results
+    }
+
+    fn sender(&self, options: &EmailOptions) -> String {
+        options.sender.as_ref().unwrap_or(&self.sender).to_string()
+    }
+
+    /// Posts `body` to the provider once the send budget allows an email to
+    /// each of `recipients`. While the circuit is open it fails fast, without
+    /// waiting for or spending the budget, and it fails as well if the budget
+    /// would take longer than `MAX_SEND_BUDGET_WAIT` to allow the request.
+    async fn post<'r, T: serde::Serialize + ?Sized>(
+        &self,
+        url: &str,
+        body: &T,
+        recipients: impl IntoIterator<Item = &'r str>,
+    ) -> Result<reqwest::Response, EmailClientError> {
+        if !self.circuit_breaker.try_acquire() {
+            return Err(EmailClientError::CircuitOpen);
+        }
+        let mut acquired = Vec::new();
+        for recipient in recipients {
+            if let Err(retry_after) = self
+                .rate_limiter
+                .acquire(recipient, MAX_SEND_BUDGET_WAIT)
+                .await
+            {
+                for recipient in acquired {
+                    self.rate_limiter.release(recipient).await;
+                }
+                return Err(EmailClientError::BudgetExhausted { retry_after });
+            }
+            acquired.push(recipient);
+        }
+        let outcome = self.send(url, body).await;
+        match &outcome {
+            Err(e) if e.is_provider_failure() => self.circuit_breaker.record_failure(),
+            _ => self.circuit_breaker.record_success(),
+        }
+        outcome
+    }
+
+    async fn send<T: serde::Serialize + ?Sized>(
+        &self,
+        url: &str,
+        body: &T,
+    ) -> Result<reqwest::Response, EmailClientError> {
+        let response = self
             .http_client
             .post(url)
-            .json(&request_body)
-            .header("X-Postmark-Server-Token", &self.authorization_token)
+            .json(body)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .headers(trace_context_headers())
             .send()
-            .await?
-            .error_for_status()?;
-
-        Ok(())
+            .await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+                .map(Duration::from_secs);
+            return Err(EmailClientError::RateLimited { retry_after });
+        }
+        let error = response
+            .json::<ErrorResponse>()
+            .await
+            .unwrap_or_else(|_| ErrorResponse {
+                error_code: 0,
+                message: status.canonical_reason().unwrap_or_default().to_owned(),
+            });
+        Err(EmailClientError::rejection(
+            status,
+            error.error_code,
+            error.message,
+        ))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{domain::SubscriberEmail, email_client::EmailClient};
+    use crate::{
+        circuit_breaker::CircuitState,
+        configuration::{CircuitBreakerSettings, RateLimitSettings},
+        domain::{Mailbox, SubscriberEmail, SubscriberName},
+        email_client::{
+            batch_ranges, Attachment, BatchEmail, EmailClient, EmailClientError, EmailOptions,
+            SendEmailRequest, MAX_ATTACHMENTS_SIZE, MAX_BATCH_PAYLOAD_SIZE, MAX_BATCH_SIZE,
+        },
+    };
     use claim::{assert_err, assert_ok};
     use fake::faker::lorem::en::{Paragraph, Sentence};
     use fake::Faker;
     use fake::{faker::internet::en::SafeEmail, Fake};
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::TracerProvider};
+    use secrecy::Secret;
+    use tracing::Instrument;
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
     use wiremock::matchers::{any, header, header_exists, method, path};
     use wiremock::{Match, Mock, MockServer, ResponseTemplate};
 
@@ -90,6 +826,15 @@ mod tests {
         }
     }
 
+    struct BatchSizeMatcher(usize);
+
+    impl Match for BatchSizeMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            let result: Result<Vec<serde_json::Value>, _> = serde_json::from_slice(&request.body);
+            matches!(result, Ok(body) if body.len() == self.0)
+        }
+    }
+
     fn subject() -> String {
         Sentence(1..2).fake()
     }
@@ -98,19 +843,56 @@ mod tests {
         Paragraph(1..10).fake()
     }
 
-    fn email() -> SubscriberEmail {
-        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    fn email() -> Mailbox {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap().into()
     }
 
     fn email_client(base_url: String) -> EmailClient {
         EmailClient::new(
             base_url,
             email(),
-            Faker.fake(),
+            Secret::new(Faker.fake()),
             std::time::Duration::from_millis(200),
+            true,
+            &RateLimitSettings::default(),
+            &CircuitBreakerSettings {
+                failure_threshold: 2,
+                open_duration_milliseconds: 60_000,
+            },
         )
     }
 
+    fn batch(size: usize) -> Vec<BatchEmail> {
+        (0..size)
+            .map(|_| BatchEmail {
+                recipient: email(),
+                subject: subject(),
+                html_content: content(),
+                text_content: content(),
+            })
+            .collect()
+    }
+
+    fn batch_response(error_codes: &[i64]) -> ResponseTemplate {
+        let body: Vec<_> = error_codes
+            .iter()
+            .map(|code| {
+                serde_json::json!({
+                    "ErrorCode": code,
+                    "Message": if *code == 0 { "OK" } else { "Invalid 'To' address." },
+                })
+            })
+            .collect();
+        ResponseTemplate::new(200).set_body_json(body)
+    }
+
+    fn postmark_error(status: u16, error_code: i64, message: &str) -> ResponseTemplate {
+        ResponseTemplate::new(status).set_body_json(serde_json::json!({
+            "ErrorCode": error_code,
+            "Message": message,
+        }))
+    }
+
     #[tokio::test]
     async fn send_email_times_out_if_the_server_takes_too_long() {
         let mock_server = MockServer::start().await;
@@ -123,10 +905,10 @@ mod tests {
             .mount(&mock_server)
             .await;
         let outcome = email_client
-            .send_email(email(), &subject(), &content(), &content())
+            .send_email(&email(), &subject(), &content(), &content())
             .await;
 
-        assert_err!(outcome);
+        assert!(matches!(outcome, Err(EmailClientError::Timeout(_))));
     }
 
     #[tokio::test]
@@ -141,7 +923,7 @@ mod tests {
             .await;
 
         let response = email_client
-            .send_email(email(), &subject(), &content(), &content())
+            .send_email(&email(), &subject(), &content(), &content())
             .await;
 
         assert_err!(response);
@@ -159,7 +941,7 @@ mod tests {
             .await;
 
         let response = email_client
-            .send_email(email(), &subject(), &content(), &content())
+            .send_email(&email(), &subject(), &content(), &content())
             .await;
 
         assert_ok!(response);
@@ -181,7 +963,560 @@ mod tests {
             .await;
 
         let _ = email_client
-            .send_email(email(), &subject(), &content(), &content())
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+    }
+
+    #[tokio::test]
+    async fn send_email_propagates_the_current_trace() {
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let provider = TracerProvider::builder().build();
+        let subscriber = Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let _default = tracing::subscriber::set_default(subscriber);
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("traceparent"))
+            .and(path("/email"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .instrument(tracing::info_span!("Confirm a new subscriber"))
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_batch_reports_per_message_failures() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let emails = batch(3);
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(method("POST"))
+            .and(path("/email/batch"))
+            .respond_with(batch_response(&[0, 300, 0]))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let results = email_client
+            .send_batch(&emails, &EmailOptions::default())
+            .await;
+
+        assert_eq!(3, results.len());
+        assert!(results[0].is_success());
+        assert!(matches!(
+            results[1].outcome.as_ref().map_err(AsRef::as_ref),
+            Err(EmailClientError::InvalidRecipient {
+                error_code: 300,
+                ..
+            })
+        ));
+        assert_eq!(emails[1].recipient.email().as_ref(), results[1].recipient);
+        assert!(results[2].is_success());
+    }
+
+    #[tokio::test]
+    async fn send_batch_splits_large_batches() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let emails = batch(MAX_BATCH_SIZE + 1);
+
+        Mock::given(path("/email/batch"))
+            .and(BatchSizeMatcher(MAX_BATCH_SIZE))
+            .respond_with(batch_response(&[0; MAX_BATCH_SIZE]))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(path("/email/batch"))
+            .and(BatchSizeMatcher(1))
+            .respond_with(batch_response(&[0]))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let results = email_client
+            .send_batch(&emails, &EmailOptions::default())
+            .await;
+
+        assert_eq!(MAX_BATCH_SIZE + 1, results.len());
+        assert!(results.iter().all(|r| r.is_success()));
+    }
+
+    #[test]
+    fn batches_stay_within_the_payload_limit() {
+        let attachment = MAX_ATTACHMENTS_SIZE.div_ceil(3) * 4;
+
+        assert_eq!(vec![0..3, 3..6, 6..7], batch_ranges(&[attachment; 7]));
+        assert_eq!(
+            vec![0..MAX_BATCH_SIZE, MAX_BATCH_SIZE..MAX_BATCH_SIZE + 1],
+            batch_ranges(&[1; MAX_BATCH_SIZE + 1])
+        );
+        assert_eq!(
+            vec![0..1, 1..2],
+            batch_ranges(&[MAX_BATCH_PAYLOAD_SIZE + 1, 1])
+        );
+        // Two halves of the limit leave no room for the brackets and the comma.
+        assert_eq!(
+            vec![0..1, 1..2],
+            batch_ranges(&[MAX_BATCH_PAYLOAD_SIZE / 2; 2])
+        );
+        assert!(batch_ranges(&[]).is_empty());
+    }
+
+    #[test]
+    fn the_payload_size_accounts_for_encoded_attachments() {
+        let email = &batch(1)[0];
+        let options = EmailOptions {
+            attachments: vec![
+                Attachment::new(
+                    "the \"final\" report.pdf".into(),
+                    "application/pdf".into(),
+                    vec![0; 3000],
+                ),
+                Attachment::inline(
+                    "logo.png".into(),
+                    "image/png".into(),
+                    vec![0; 1000],
+                    "logo".into(),
+                ),
+            ],
+            ..EmailOptions::default()
+        };
+
+        let estimated = SendEmailRequest::batched("sender", email, &options).payload_size();
+        let actual = serde_json::to_vec(&SendEmailRequest::batched("sender", email, &options))
+            .unwrap()
+            .len();
+
+        assert!(estimated >= actual, "{} vs {}", estimated, actual);
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_every_message_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let results = email_client
+            .send_batch(&batch(2), &EmailOptions::default())
+            .await;
+
+        assert_eq!(2, results.len());
+        for result in results {
+            let error = assert_err!(result.outcome);
+            assert!(error.is_transient());
+        }
+    }
+
+    #[tokio::test]
+    async fn send_batch_keeps_the_results_of_the_requests_sent_before_a_failure() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let emails = batch(MAX_BATCH_SIZE + 1);
+
+        Mock::given(path("/email/batch"))
+            .and(BatchSizeMatcher(MAX_BATCH_SIZE))
+            .respond_with(batch_response(&[0; MAX_BATCH_SIZE]))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(path("/email/batch"))
+            .and(BatchSizeMatcher(1))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let results = email_client
+            .send_batch(&emails, &EmailOptions::default())
+            .await;
+
+        assert_eq!(MAX_BATCH_SIZE + 1, results.len());
+        assert!(results[..MAX_BATCH_SIZE].iter().all(|r| r.is_success()));
+        assert!(!results[MAX_BATCH_SIZE].is_success());
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_fast_once_the_circuit_is_open() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        for _ in 0..2 {
+            let outcome = email_client
+                .send_email(&email(), &subject(), &content(), &content())
+                .await;
+            assert!(matches!(
+                outcome,
+                Err(EmailClientError::Rejected { status, .. }) if status.as_u16() == 500
+            ));
+        }
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert!(matches!(outcome, Err(EmailClientError::CircuitOpen)));
+    }
+
+    #[tokio::test]
+    async fn an_open_circuit_fails_fast_without_spending_the_send_budget() {
+        let mock_server = MockServer::start().await;
+        let email_client = EmailClient::new(
+            mock_server.uri(),
+            email(),
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+            true,
+            &RateLimitSettings {
+                per_hour: Some(2),
+                ..RateLimitSettings::default()
+            },
+            &CircuitBreakerSettings {
+                failure_threshold: 2,
+                open_duration_milliseconds: 60_000,
+            },
+        );
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        for _ in 0..2 {
+            let outcome = email_client
+                .send_email(&email(), &subject(), &content(), &content())
+                .await;
+            assert_err!(outcome);
+        }
+        // The hourly budget is spent: waiting for it would take an hour.
+        let outcome = tokio::time::timeout(
+            std::time::Duration::from_secs(1),
+            email_client.send_email(&email(), &subject(), &content(), &content()),
+        )
+        .await
+        .expect("An open circuit should not wait for the send budget.");
+
+        assert!(matches!(outcome, Err(EmailClientError::CircuitOpen)));
+    }
+
+    #[tokio::test]
+    async fn throttling_does_not_open_the_circuit() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        for _ in 0..3 {
+            let outcome = email_client
+                .send_email(&email(), &subject(), &content(), &content())
+                .await;
+            assert!(matches!(outcome, Err(EmailClientError::RateLimited { .. })));
+        }
+        assert_eq!(CircuitState::Closed, email_client.circuit_state());
+    }
+
+    #[tokio::test]
+    async fn client_errors_do_not_open_the_circuit() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(postmark_error(422, 300, "Invalid 'From' field."))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        for _ in 0..3 {
+            let outcome = email_client
+                .send_email(&email(), &subject(), &content(), &content())
+                .await;
+            assert!(matches!(outcome, Err(EmailClientError::Rejected { .. })));
+        }
+    }
+
+    #[tokio::test]
+    async fn send_email_reports_the_provider_error_code() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(postmark_error(422, 405, "Not allowed to send."))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        let e = assert_err!(outcome);
+        assert!(!e.is_transient());
+        assert!(matches!(
+            e,
+            EmailClientError::Rejected { error_code: 405, ref message, .. }
+                if message.expose() == "Not allowed to send."
+        ));
+    }
+
+    #[tokio::test]
+    async fn inactive_recipients_are_reported_as_invalid() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(postmark_error(
+                422,
+                406,
+                "You tried to send to a recipient that has been marked as inactive.",
+            ))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert!(matches!(
+            outcome,
+            Err(EmailClientError::InvalidRecipient {
+                error_code: 406,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn only_malformed_recipient_addresses_are_reported_as_invalid() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(postmark_error(422, 300, "Invalid 'To' address: 'nope'."))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(postmark_error(422, 300, "Invalid 'Reply-To' address."))
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+        assert!(matches!(
+            outcome,
+            Err(EmailClientError::InvalidRecipient { .. })
+        ));
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+        let e = assert_err!(outcome);
+        assert!(!e.is_transient());
+        assert!(matches!(
+            e,
+            EmailClientError::Rejected {
+                error_code: 300,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn throttled_requests_are_reported_as_rate_limited() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "30"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        let e = assert_err!(outcome);
+        assert!(e.is_transient());
+        assert!(matches!(
+            e,
+            EmailClientError::RateLimited { retry_after: Some(retry_after) }
+                if retry_after == std::time::Duration::from_secs(30)
+        ));
+    }
+
+    #[tokio::test]
+    async fn unreachable_providers_are_reported_as_connection_errors() {
+        let email_client = email_client("http://127.0.0.1:1".into());
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        let e = assert_err!(outcome);
+        assert!(e.is_transient());
+        assert!(matches!(e, EmailClientError::Connection(_)));
+    }
+
+    #[tokio::test]
+    async fn send_email_includes_the_options_in_the_request() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let options = EmailOptions {
+            sender: Some(Mailbox::new(
+                SubscriberEmail::parse("news@example.com".into()).unwrap(),
+                Some(SubscriberName::parse("The Editors".into()).unwrap()),
+            )),
+            reply_to: Some(SubscriberEmail::parse("editor@example.com".into()).unwrap()),
+            cc: vec![
+                SubscriberEmail::parse("a@example.com".into()).unwrap(),
+                SubscriberEmail::parse("b@example.com".into()).unwrap(),
+            ],
+            bcc: vec![SubscriberEmail::parse("c@example.com".into()).unwrap()],
+            tag: Some("welcome".into()),
+            metadata: [("user".to_string(), "42".to_string())].into(),
+            attachments: vec![Attachment::inline(
+                "logo.png".into(),
+                "image/png".into(),
+                b"hello".to_vec(),
+                "logo".into(),
+            )],
+        };
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        assert_ok!(
+            email_client
+                .send_email_with_options(&email(), &subject(), &content(), &content(), &options)
+                .await
+        );
+
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(r#""The Editors" <news@example.com>"#, body["From"]);
+        assert_eq!("editor@example.com", body["ReplyTo"]);
+        assert_eq!("a@example.com,b@example.com", body["Cc"]);
+        assert_eq!("c@example.com", body["Bcc"]);
+        assert_eq!("welcome", body["Tag"]);
+        assert_eq!("42", body["Metadata"]["user"]);
+        assert_eq!(
+            serde_json::json!([{
+                "Name": "logo.png",
+                "Content": "aGVsbG8=",
+                "ContentType": "image/png",
+                "ContentID": "cid:logo",
+            }]),
+            body["Attachments"]
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_renders_display_names() {
+        let mock_server = MockServer::start().await;
+        let sender = Mailbox::new(
+            SubscriberEmail::parse("sender@example.com".into()).unwrap(),
+            Some(SubscriberName::parse("Zero To Production".into()).unwrap()),
+        );
+        let email_client = EmailClient::new(
+            mock_server.uri(),
+            sender,
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+            true,
+            &RateLimitSettings::default(),
+            &CircuitBreakerSettings::default(),
+        );
+        let recipient = Mailbox::new(
+            SubscriberEmail::parse("ursula@example.com".into()).unwrap(),
+            Some(SubscriberName::parse("Ursula Le Guin".into()).unwrap()),
+        );
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let _ = email_client
+            .send_email(&recipient, &subject(), &content(), &content())
+            .await;
+
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(r#""Zero To Production" <sender@example.com>"#, body["From"]);
+        assert_eq!(r#""Ursula Le Guin" <ursula@example.com>"#, body["To"]);
+    }
+
+    #[tokio::test]
+    async fn send_email_omits_unset_options() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
             .await;
+
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        for field in ["ReplyTo", "Cc", "Bcc", "Tag", "Metadata", "Attachments"] {
+            assert!(body.get(field).is_none(), "{} should be omitted", field);
+        }
+    }
+
+    #[test]
+    fn options_over_the_provider_limits_are_rejected() {
+        let too_many_fields = EmailOptions {
+            metadata: (0..11).map(|i| (i.to_string(), "v".to_string())).collect(),
+            ..EmailOptions::default()
+        };
+        let value_too_long = EmailOptions {
+            metadata: [("key".to_string(), "v".repeat(81))].into(),
+            ..EmailOptions::default()
+        };
+        let attachments_too_large = EmailOptions {
+            attachments: vec![Attachment::new(
+                "big.bin".into(),
+                "application/octet-stream".into(),
+                vec![0; 10 * 1024 * 1024 + 1],
+            )],
+            ..EmailOptions::default()
+        };
+
+        assert_ok!(EmailOptions::default().validate());
+        assert_err!(too_many_fields.validate());
+        assert_err!(value_too_long.validate());
+        assert_err!(attachments_too_large.validate());
     }
 }
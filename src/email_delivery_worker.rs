@@ -0,0 +1,559 @@
+use crate::{
+    configuration::{EmailOutboxSettings, Settings},
+    domain::{Mailbox, SubscriberEmail, SubscriberName},
+    email_client::{Attachment, EmailClient, EmailClientError, EmailOptions},
+    shutdown::ShutdownSignal,
+    startup::get_connection_pool,
+    telemetry::{current_traceparent, set_parent_from_traceparent},
+};
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{field::display, Instrument, Span};
+use uuid::Uuid;
+
+/// Retries back off exponentially, capped at one hour between attempts.
+const MAX_BACKOFF_SECONDS: i64 = 3600;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+struct QueuedEmail {
+    email_id: Uuid,
+    recipient: String,
+    recipient_name: Option<String>,
+    subject: String,
+    html_body: String,
+    text_body: String,
+    n_retries: i32,
+    traceparent: Option<String>,
+    options: Option<String>,
+}
+
+/// How `EmailOptions` are written to the outbox, with attachments base64-encoded.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoredOptions {
+    sender: Option<StoredMailbox>,
+    reply_to: Option<String>,
+    cc: Vec<String>,
+    bcc: Vec<String>,
+    tag: Option<String>,
+    metadata: HashMap<String, String>,
+    attachments: Vec<StoredAttachment>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoredMailbox {
+    email: String,
+    name: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoredAttachment {
+    name: String,
+    content_type: String,
+    content: String,
+    content_id: Option<String>,
+}
+
+impl From<&EmailOptions> for StoredOptions {
+    fn from(options: &EmailOptions) -> Self {
+        let addresses = |addresses: &[SubscriberEmail]| {
+            addresses.iter().map(|a| a.as_ref().to_owned()).collect()
+        };
+        Self {
+            sender: options.sender.as_ref().map(|sender| StoredMailbox {
+                email: sender.email().as_ref().to_owned(),
+                name: sender.name().map(|name| name.as_ref().to_owned()),
+            }),
+            reply_to: options.reply_to.as_ref().map(|a| a.as_ref().to_owned()),
+            cc: addresses(&options.cc),
+            bcc: addresses(&options.bcc),
+            tag: options.tag.clone(),
+            metadata: options.metadata.clone(),
+            attachments: options
+                .attachments
+                .iter()
+                .map(|attachment| StoredAttachment {
+                    name: attachment.name.clone(),
+                    content_type: attachment.content_type.clone(),
+                    content: base64::encode(&attachment.content),
+                    content_id: attachment.content_id.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<StoredOptions> for EmailOptions {
+    type Error = String;
+
+    fn try_from(options: StoredOptions) -> Result<Self, Self::Error> {
+        let addresses = |addresses: Vec<String>| {
+            addresses
+                .into_iter()
+                .map(SubscriberEmail::parse)
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let sender = options
+            .sender
+            .map(|sender| -> Result<Mailbox, String> {
+                let name = sender.name.map(SubscriberName::parse).transpose()?;
+                Ok(Mailbox::new(SubscriberEmail::parse(sender.email)?, name))
+            })
+            .transpose()?;
+        let attachments = options
+            .attachments
+            .into_iter()
+            .map(|attachment| {
+                let content = base64::decode(&attachment.content)
+                    .map_err(|e| format!("Invalid attachment content: {}", e))?;
+                Ok(Attachment {
+                    name: attachment.name,
+                    content_type: attachment.content_type,
+                    content,
+                    content_id: attachment.content_id,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(EmailOptions {
+            sender,
+            reply_to: options.reply_to.map(SubscriberEmail::parse).transpose()?,
+            cc: addresses(options.cc)?,
+            bcc: addresses(options.bcc)?,
+            tag: options.tag,
+            metadata: options.metadata,
+            attachments,
+        })
+    }
+}
+
+fn parse_options(options: Option<String>) -> Result<EmailOptions, String> {
+    match options {
+        Some(options) => serde_json::from_str::<StoredOptions>(&options)
+            .map_err(|e| format!("Invalid stored email options: {}", e))?
+            .try_into(),
+        None => Ok(EmailOptions::default()),
+    }
+}
+
+/// When the delivery worker last polled the outbox, reported by `/health/ready`.
+#[derive(Debug, Default)]
+pub struct WorkerHeartbeat(Mutex<Option<Instant>>);
+
+impl WorkerHeartbeat {
+    pub fn beat(&self) {
+        *self.0.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// `None` until the worker has polled the outbox once.
+    pub fn since_last_beat(&self) -> Option<Duration> {
+        self.0.lock().unwrap().map(|beat| beat.elapsed())
+    }
+}
+
+/// Delivers the emails written to the `email_outbox` table once the
+/// transaction that produced them has committed, until `shutdown` is triggered.
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+    email_client: Arc<EmailClient>,
+    heartbeat: Arc<WorkerHeartbeat>,
+    shutdown: ShutdownSignal,
+) -> Result<(), std::io::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    worker_loop(
+        connection_pool.clone(),
+        email_client,
+        configuration.email_outbox,
+        heartbeat,
+        shutdown,
+    )
+    .await?;
+    // Closing the pool releases the locks of any connection still open.
+    connection_pool.close().await;
+    Ok(())
+}
+
+/// Shutdown is only checked between tasks: the email being delivered when it
+/// is triggered is still sent and removed from the outbox.
+pub async fn worker_loop(
+    pool: PgPool,
+    email_client: Arc<EmailClient>,
+    settings: EmailOutboxSettings,
+    heartbeat: Arc<WorkerHeartbeat>,
+    mut shutdown: ShutdownSignal,
+) -> Result<(), std::io::Error> {
+    while !shutdown.is_triggered() {
+        heartbeat.beat();
+        let pause = match try_execute_task(&pool, &email_client, &settings).await {
+            Ok(ExecutionOutcome::EmptyQueue) => settings.poll_interval(),
+            Err(_) => Duration::from_secs(1),
+            Ok(ExecutionOutcome::TaskCompleted) => continue,
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(pause) => {}
+            _ = shutdown.triggered() => {}
+        }
+    }
+    tracing::info!("The delivery worker has stopped");
+    Ok(())
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(email_id=tracing::field::Empty, n_retries=tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    settings: &EmailOutboxSettings,
+) -> Result<ExecutionOutcome, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let task = match dequeue_task(&mut transaction).await? {
+        Some(task) => task,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    Span::current()
+        .record("email_id", display(task.email_id))
+        .record("n_retries", display(task.n_retries));
+    match SubscriberEmail::parse(task.recipient).and_then(|email| {
+        let options = parse_options(task.options)?;
+        Ok((email, options))
+    }) {
+        Ok((email, options)) => {
+            // A name that no longer parses should not hold up delivery.
+            let name = task
+                .recipient_name
+                .and_then(|name| SubscriberName::parse(name).ok());
+            let recipient = Mailbox::new(email, name);
+            // Continue the trace of the request that queued the email.
+            let delivery_span = tracing::info_span!("Deliver a queued email");
+            if let Some(traceparent) = &task.traceparent {
+                set_parent_from_traceparent(&delivery_span, traceparent);
+            }
+            match email_client
+                .send_email_with_options(
+                    &recipient,
+                    &task.subject,
+                    &task.html_body,
+                    &task.text_body,
+                    &options,
+                )
+                .instrument(delivery_span)
+                .await
+            {
+                Ok(()) => delete_task(&mut transaction, task.email_id).await?,
+                Err(e @ EmailClientError::InvalidRecipient { .. }) => {
+                    tracing::warn!(
+                        error.message = %e,
+                        "Suppressing a queued email. The provider will not deliver to its recipient.",
+                    );
+                    delete_task(&mut transaction, task.email_id).await?;
+                }
+                // Nothing was sent, so the attempt does not count.
+                Err(EmailClientError::CircuitOpen) => {
+                    tracing::info!(
+                        "Postponing a queued email. The email provider circuit is open.",
+                    );
+                    postpone_task(
+                        &mut transaction,
+                        task.email_id,
+                        email_client.circuit_retry_after(),
+                    )
+                    .await?;
+                }
+                Err(EmailClientError::BudgetExhausted { retry_after }) => {
+                    tracing::info!(
+                        "Postponing a queued email. The email send budget is exhausted."
+                    );
+                    postpone_task(&mut transaction, task.email_id, retry_after).await?;
+                }
+                Err(e)
+                    if !e.is_transient() || task.n_retries + 1 >= settings.max_attempts as i32 =>
+                {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to deliver a queued email. Giving up after {} attempts.",
+                        task.n_retries + 1
+                    );
+                    dead_letter_task(&mut transaction, task.email_id, &e.to_string()).await?;
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to deliver a queued email. Scheduling a retry.",
+                    );
+                    let retry_after = match e {
+                        EmailClientError::RateLimited { retry_after } => retry_after,
+                        _ => None,
+                    };
+                    reschedule_task(
+                        &mut transaction,
+                        task.email_id,
+                        task.n_retries,
+                        retry_after,
+                        &e.to_string(),
+                    )
+                    .await?;
+                }
+            }
+        }
+        Err(e) => {
+            tracing::error!(
+                error.message = %e,
+                "Skipping a queued email. The stored recipient or options are invalid.",
+            );
+            delete_task(&mut transaction, task.email_id).await?;
+        }
+    }
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+#[tracing::instrument(
+    name = "Write an email to the outbox",
+    skip(transaction, recipient, subject, html_body, text_body)
+)]
+pub async fn enqueue_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    recipient: &Mailbox,
+    subject: &str,
+    html_body: &str,
+    text_body: &str,
+) -> Result<(), sqlx::Error> {
+    insert_task(transaction, recipient, subject, html_body, text_body, None).await
+}
+
+/// Queues an email that is sent with `options`, e.g. a newsletter issue the
+/// provider failed to take.
+#[tracing::instrument(
+    name = "Write an email to the outbox",
+    skip(transaction, recipient, subject, html_body, text_body, options)
+)]
+pub async fn enqueue_email_with_options(
+    transaction: &mut Transaction<'_, Postgres>,
+    recipient: &Mailbox,
+    subject: &str,
+    html_body: &str,
+    text_body: &str,
+    options: &EmailOptions,
+) -> Result<(), sqlx::Error> {
+    let options = serde_json::to_string(&StoredOptions::from(options))
+        .expect("Email options can always be serialized");
+    insert_task(
+        transaction,
+        recipient,
+        subject,
+        html_body,
+        text_body,
+        Some(options),
+    )
+    .await
+}
+
+async fn insert_task(
+    transaction: &mut Transaction<'_, Postgres>,
+    recipient: &Mailbox,
+    subject: &str,
+    html_body: &str,
+    text_body: &str,
+    options: Option<String>,
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO email_outbox (
+            email_id, recipient, recipient_name, subject, html_body, text_body,
+            execute_after, created_at, traceparent, options
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $7, $8, $9)
+        "#,
+        Uuid::new_v4(),
+        recipient.email().as_ref(),
+        recipient.name().map(AsRef::as_ref),
+        subject,
+        html_body,
+        text_body,
+        now,
+        current_traceparent(),
+        options
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Option<QueuedEmail>, sqlx::Error> {
+    let task = sqlx::query_as!(
+        QueuedEmail,
+        r#"
+        SELECT
+            email_id, recipient, recipient_name, subject, html_body, text_body, n_retries,
+            traceparent, options
+        FROM email_outbox
+        WHERE execute_after <= now()
+        ORDER BY execute_after
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(task)
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    transaction: &mut Transaction<'_, Postgres>,
+    email_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(r#"DELETE FROM email_outbox WHERE email_id = $1"#, email_id)
+        .execute(&mut **transaction)
+        .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn reschedule_task(
+    transaction: &mut Transaction<'_, Postgres>,
+    email_id: Uuid,
+    n_retries: i32,
+    retry_after: Option<Duration>,
+    last_error: &str,
+) -> Result<(), sqlx::Error> {
+    let backoff = 2_i64
+        .checked_pow(n_retries as u32)
+        .unwrap_or(MAX_BACKOFF_SECONDS)
+        .min(MAX_BACKOFF_SECONDS);
+    // Wait at least as long as the provider asked us to.
+    let backoff = retry_after
+        .map(|retry_after| retry_after.as_secs() as i64)
+        .map_or(backoff, |retry_after| backoff.max(retry_after));
+    sqlx::query!(
+        r#"
+        UPDATE email_outbox
+        SET n_retries = n_retries + 1, execute_after = $2, last_error = $3
+        WHERE email_id = $1
+        "#,
+        email_id,
+        Utc::now() + chrono::Duration::seconds(backoff),
+        last_error
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// Delays a task without using up one of its attempts.
+#[tracing::instrument(skip_all)]
+async fn postpone_task(
+    transaction: &mut Transaction<'_, Postgres>,
+    email_id: Uuid,
+    delay: Duration,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE email_outbox SET execute_after = $2 WHERE email_id = $1"#,
+        email_id,
+        Utc::now() + chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::zero())
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// Moves a task that exhausted its delivery attempts out of the outbox and
+/// into `email_dead_letters`, where it waits for an operator.
+#[tracing::instrument(skip_all)]
+async fn dead_letter_task(
+    transaction: &mut Transaction<'_, Postgres>,
+    email_id: Uuid,
+    last_error: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        WITH dead AS (
+            DELETE FROM email_outbox
+            WHERE email_id = $1
+            RETURNING email_id, recipient, recipient_name, subject, html_body, text_body,
+                options, n_retries, created_at
+        )
+        INSERT INTO email_dead_letters (
+            email_id, recipient, recipient_name, subject, html_body, text_body,
+            options, n_attempts, last_error, created_at, failed_at
+        )
+        SELECT email_id, recipient, recipient_name, subject, html_body, text_body,
+            options, n_retries + 1, $2, created_at, $3
+        FROM dead
+        "#,
+        email_id,
+        last_error,
+        Utc::now()
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// Puts a dead letter back into the outbox with a fresh attempt budget.
+/// Returns `false` if there is no dead letter with that id.
+#[tracing::instrument(name = "Replay a dead-lettered email", skip(pool))]
+pub async fn replay_dead_letter(pool: &PgPool, email_id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        WITH replayed AS (
+            DELETE FROM email_dead_letters
+            WHERE email_id = $1
+            RETURNING email_id, recipient, recipient_name, subject, html_body, text_body,
+                options, created_at
+        )
+        INSERT INTO email_outbox (
+            email_id, recipient, recipient_name, subject, html_body, text_body,
+            options, execute_after, created_at
+        )
+        SELECT email_id, recipient, recipient_name, subject, html_body, text_body,
+            options, $2, created_at
+        FROM replayed
+        "#,
+        email_id,
+        Utc::now()
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[derive(serde::Serialize, Debug, PartialEq, Eq)]
+pub struct QueueDepth {
+    /// Emails due for delivery right now.
+    pub ready: i64,
+    /// Emails waiting out a retry backoff.
+    pub retrying: i64,
+    pub dead_letters: i64,
+}
+
+#[tracing::instrument(name = "Measure the email queue depth", skip(pool))]
+pub async fn queue_depth(pool: &PgPool) -> Result<QueueDepth, sqlx::Error> {
+    let depth = sqlx::query_as!(
+        QueueDepth,
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM email_outbox WHERE execute_after <= now()) AS "ready!",
+            (SELECT COUNT(*) FROM email_outbox WHERE execute_after > now()) AS "retrying!",
+            (SELECT COUNT(*) FROM email_dead_letters) AS "dead_letters!"
+        "#,
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(depth)
+}
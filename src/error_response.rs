@@ -0,0 +1,89 @@
+use crate::request_id;
+use actix_web::{error::InternalError, http::StatusCode, HttpRequest, HttpResponse, ResponseError};
+use std::fmt::{Debug, Display};
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Shown instead of the error itself when it is our fault.
+pub const INTERNAL_ERROR_MESSAGE: &str =
+    "Something went wrong on our side, please quote the request id when contacting support.";
+
+/// An RFC 7807 problem details body. `code` and `request_id` are extension
+/// members: `code` is stable across releases, unlike `detail`, and
+/// `request_id` matches the response with its log lines.
+#[derive(serde::Serialize)]
+struct ProblemDetails<'a> {
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: &'a str,
+    code: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+/// The error response of every route. Problems are told apart by `code`, so
+/// `type` is `about:blank` and `title` the reason phrase of `status`.
+pub fn problem(status: StatusCode, code: &str, detail: &str) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type(PROBLEM_JSON)
+        .json(ProblemDetails {
+            problem_type: "about:blank",
+            title: status.canonical_reason().unwrap_or("Unknown Status"),
+            status: status.as_u16(),
+            detail,
+            code,
+            request_id: request_id::current().map(|id| id.to_string()),
+        })
+}
+
+/// A 500 hiding `cause` from the client. `cause` still reaches the logs.
+pub fn internal_error<E>(cause: E, code: &str) -> actix_web::Error
+where
+    E: Debug + Display + 'static,
+{
+    let response = problem(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        code,
+        INTERNAL_ERROR_MESSAGE,
+    );
+    InternalError::from_response(cause, response).into()
+}
+
+/// An error handler for the `Query`, `Form`, `Json` and `Path` extractors,
+/// which otherwise answer with a plain text body.
+pub fn extractor_error<E>(code: &'static str) -> impl Fn(E, &HttpRequest) -> actix_web::Error
+where
+    E: ResponseError + 'static,
+{
+    move |e, _| {
+        let response = problem(e.status_code(), code, &e.to_string());
+        InternalError::from_response(e, response).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error_response::{problem, PROBLEM_JSON};
+    use actix_web::{body::to_bytes, http::header::CONTENT_TYPE, http::StatusCode};
+
+    #[tokio::test]
+    async fn problems_follow_rfc_7807() {
+        let response = problem(StatusCode::NOT_FOUND, "not_found", "Nothing here.");
+
+        assert_eq!(PROBLEM_JSON, response.headers().get(CONTENT_TYPE).unwrap());
+        let body = to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Not Found",
+                "status": 404,
+                "detail": "Nothing here.",
+                "code": "not_found",
+            }),
+            body
+        );
+    }
+}
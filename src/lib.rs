@@ -1,6 +1,17 @@
+pub mod authentication;
+pub mod circuit_breaker;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod email_delivery_worker;
+pub mod error_response;
+pub mod metrics;
+pub mod rate_limiter;
+pub mod redaction;
+pub mod request_id;
 pub mod routes;
+pub mod runtime_settings;
+pub mod shutdown;
 pub mod startup;
 pub mod telemetry;
+pub mod tracking;
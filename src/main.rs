@@ -1,17 +1,122 @@
+use std::fmt::{Debug, Display};
+use tokio::task::JoinError;
 use zero2prod::{
     configuration::get_configuration,
+    email_delivery_worker::run_worker_until_stopped,
+    redaction,
+    runtime_settings::reload_on_sighup,
+    shutdown::{wait_for_termination, Shutdown},
     startup::Application,
-    telemetry::{get_subscriber, init_subscriber},
+    telemetry::{get_subscriber, init_subscriber, otlp_tracer},
 };
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let subscriber = get_subscriber("zero2prod", "info", std::io::stdout);
-    init_subscriber(subscriber);
     let configuration = get_configuration().expect("Failed to read configuration.");
-    let application = Application::build(configuration)
+    redaction::set_policy(
+        configuration.log.redaction,
+        configuration.log.redaction_key.clone(),
+    );
+    let tracer = if configuration.otlp.enabled {
+        Some(
+            otlp_tracer("zero2prod", &configuration.otlp)
+                .expect("Failed to build the OTLP exporter."),
+        )
+    } else {
+        None
+    };
+    let (subscriber, log_filter) =
+        get_subscriber("zero2prod", &configuration.log, std::io::stdout, tracer)
+            .expect("Failed to open the log file.");
+    init_subscriber(subscriber);
+    if let Err(e) = configuration.validate() {
+        // Log the readable report, the returned error is only printed with `Debug`.
+        tracing::error!("{}", e);
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, e));
+    }
+    let application = Application::build(configuration.clone())
         .await
         .expect("Failed to build application");
-    application.run_until_stopped().await?;
+    let mut runtime_reloader = application.runtime_reloader();
+    // `RUST_LOG` overrides the configured filter, a reload must not undo that.
+    if std::env::var("RUST_LOG").is_err() {
+        runtime_reloader = runtime_reloader.with_log_filter(log_filter);
+    }
+    let shutdown = Shutdown::new();
+    let shutdown_timeout = configuration.application.shutdown_timeout();
+    let server = application.server_handle();
+    let mut worker_task = tokio::spawn(run_worker_until_stopped(
+        configuration,
+        application.email_client(),
+        application.worker_heartbeat(),
+        shutdown.subscribe(),
+    ));
+    let mut application_task = tokio::spawn(application.run_until_stopped());
+    let reload_task = tokio::spawn(reload_on_sighup(runtime_reloader));
+
+    let terminated = tokio::select! {
+        o = &mut application_task => {
+            report_exit("API", o);
+            false
+        }
+        o = &mut worker_task => {
+            report_exit("Background worker", o);
+            false
+        }
+        o = reload_task => {
+            report_exit("Runtime settings reloader", o);
+            false
+        }
+        o = wait_for_termination() => match o {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::error!(
+                    error.message = %e,
+                    "Failed to listen for termination signals"
+                );
+                false
+            }
+        },
+    };
+    if terminated {
+        tracing::info!("Shutting down");
+        // Stop accepting requests and deliveries, then wait for those in flight.
+        shutdown.trigger();
+        let drained = tokio::time::timeout(shutdown_timeout, async {
+            server.stop(true).await;
+            report_exit("API", application_task.await);
+            report_exit("Background worker", worker_task.await);
+        })
+        .await;
+        if drained.is_err() {
+            tracing::warn!("The shutdown deadline has passed, exiting with work in flight");
+        }
+    }
+    // Flush the spans that have not been exported yet.
+    opentelemetry::global::shutdown_tracer_provider();
     Ok(())
 }
+
+fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "'{}' task failed to complete",
+                task_name
+            )
+        }
+    }
+}
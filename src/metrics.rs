@@ -0,0 +1,218 @@
+use crate::email_delivery_worker::QueueDepth;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use sqlx::PgPool;
+use std::time::Duration;
+
+/// A step of the subscription funnel.
+#[derive(Debug, Clone, Copy)]
+pub enum SubscriptionEvent {
+    Created,
+    Confirmed,
+}
+
+impl SubscriptionEvent {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionEvent::Created => "created",
+            SubscriptionEvent::Confirmed => "confirmed",
+        }
+    }
+}
+
+/// The Prometheus metrics of one application, rendered by `GET /metrics`.
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    db_pool_connections: IntGaugeVec,
+    db_pool_max_connections: IntGauge,
+    emails: IntCounterVec,
+    subscriptions: IntCounterVec,
+    email_queue_depth: IntGaugeVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled, per route."),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time spent handling HTTP requests, per route.",
+            ),
+            &["method", "route"],
+        )
+        .unwrap();
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new(
+                "db_pool_connections",
+                "Open database connections, by whether they are idle or in use.",
+            ),
+            &["state"],
+        )
+        .unwrap();
+        let db_pool_max_connections = IntGauge::new(
+            "db_pool_max_connections",
+            "The most connections the database pool will open.",
+        )
+        .unwrap();
+        let emails = IntCounterVec::new(
+            Opts::new(
+                "emails_total",
+                "Emails handed to the email provider, per outcome.",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        let subscriptions = IntCounterVec::new(
+            Opts::new(
+                "subscriptions_total",
+                "Subscriptions reaching each step of the funnel.",
+            ),
+            &["event"],
+        )
+        .unwrap();
+        let email_queue_depth = IntGaugeVec::new(
+            Opts::new(
+                "email_queue_depth",
+                "Emails waiting in the delivery queue, per state.",
+            ),
+            &["state"],
+        )
+        .unwrap();
+
+        // Start every funnel step at zero rather than leaving it out until it happens.
+        for event in [SubscriptionEvent::Created, SubscriptionEvent::Confirmed] {
+            subscriptions.with_label_values(&[event.as_str()]);
+        }
+
+        let registry = Registry::new();
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(db_pool_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(db_pool_max_connections.clone()))
+            .unwrap();
+        registry.register(Box::new(emails.clone())).unwrap();
+        registry.register(Box::new(subscriptions.clone())).unwrap();
+        registry
+            .register(Box::new(email_queue_depth.clone()))
+            .unwrap();
+
+        Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            db_pool_connections,
+            db_pool_max_connections,
+            emails,
+            subscriptions,
+            email_queue_depth,
+        }
+    }
+
+    /// `route` is the pattern the request matched, e.g. `/archive/{slug}`,
+    /// so that the number of series stays bounded.
+    pub fn record_http_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        self.http_requests
+            .with_label_values(&[method, route, &status.to_string()])
+            .inc();
+        self.http_request_duration
+            .with_label_values(&[method, route])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn record_emails(&self, outcome: &str, count: u64) {
+        self.emails.with_label_values(&[outcome]).inc_by(count);
+    }
+
+    pub fn record_subscription(&self, event: SubscriptionEvent) {
+        self.subscriptions
+            .with_label_values(&[event.as_str()])
+            .inc();
+    }
+
+    pub fn observe_db_pool(&self, pool: &PgPool) {
+        let size = i64::from(pool.size());
+        let idle = pool.num_idle() as i64;
+        self.db_pool_connections
+            .with_label_values(&["idle"])
+            .set(idle);
+        self.db_pool_connections
+            .with_label_values(&["in_use"])
+            .set(size - idle);
+        self.db_pool_max_connections
+            .set(i64::from(pool.options().get_max_connections()));
+    }
+
+    pub fn observe_email_queue(&self, depth: &QueueDepth) {
+        self.email_queue_depth
+            .with_label_values(&["ready"])
+            .set(depth.ready);
+        self.email_queue_depth
+            .with_label_values(&["retrying"])
+            .set(depth.retrying);
+        self.email_queue_depth
+            .with_label_values(&["dead_letters"])
+            .set(depth.dead_letters);
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Failed to encode the metrics.");
+        String::from_utf8(buffer).expect("The metrics are not valid UTF8.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::{Metrics, SubscriptionEvent};
+    use std::time::Duration;
+
+    #[test]
+    fn every_funnel_step_is_rendered_before_it_happens() {
+        let metrics = Metrics::new();
+        metrics.record_subscription(SubscriptionEvent::Created);
+
+        let rendered = metrics.render();
+
+        assert!(rendered.contains(r#"subscriptions_total{event="created"} 1"#));
+        assert!(rendered.contains(r#"subscriptions_total{event="confirmed"} 0"#));
+        assert!(!rendered.contains("unsubscribed"));
+    }
+
+    #[test]
+    fn http_requests_are_labelled_by_route() {
+        let metrics = Metrics::new();
+        metrics.record_http_request("GET", "/archive/{slug}", 200, Duration::from_millis(5));
+        metrics.record_http_request("GET", "/archive/{slug}", 200, Duration::from_millis(5));
+
+        let rendered = metrics.render();
+
+        assert!(rendered.contains(
+            r#"http_requests_total{method="GET",route="/archive/{slug}",status="200"} 2"#
+        ));
+        assert!(rendered.contains(
+            r#"http_request_duration_seconds_count{method="GET",route="/archive/{slug}"} 2"#
+        ));
+    }
+}
@@ -0,0 +1,266 @@
+use crate::configuration::{RateBudget, RateLimitSettings};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_second: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: u32, period: Duration, now: Instant) -> Self {
+        let capacity = f64::from(capacity);
+        Self {
+            capacity,
+            tokens: capacity,
+            refill_per_second: capacity / period.as_secs_f64(),
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// How long to wait before a token is available.
+    fn wait_time(&self) -> Duration {
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.refill_per_second)
+        }
+    }
+}
+
+fn buckets(budget: &RateBudget, now: Instant) -> Vec<TokenBucket> {
+    let mut buckets = Vec::new();
+    if let Some(per_second) = budget.per_second {
+        buckets.push(TokenBucket::new(per_second, Duration::from_secs(1), now));
+    }
+    if let Some(per_hour) = budget.per_hour {
+        buckets.push(TokenBucket::new(per_hour, Duration::from_secs(3600), now));
+    }
+    buckets
+}
+
+struct Buckets {
+    global: Vec<TokenBucket>,
+    per_domain: HashMap<String, Vec<TokenBucket>>,
+}
+
+/// A token-bucket limiter enforcing the per-second and per-hour budgets of
+/// `RateLimitSettings`, globally and for each configured recipient domain.
+pub struct RateLimiter {
+    buckets: Mutex<Buckets>,
+}
+
+impl Buckets {
+    fn new(settings: &RateLimitSettings) -> Self {
+        let now = Instant::now();
+        let per_domain = settings
+            .per_domain
+            .iter()
+            .map(|(domain, budget)| (domain.to_lowercase(), buckets(budget, now)))
+            .collect();
+        Self {
+            global: buckets(&settings.global(), now),
+            per_domain,
+        }
+    }
+
+    /// The buckets limiting emails to `domain`.
+    fn applicable(&mut self, domain: &str) -> Vec<&mut TokenBucket> {
+        self.global
+            .iter_mut()
+            .chain(self.per_domain.get_mut(domain).into_iter().flatten())
+            .collect()
+    }
+}
+
+impl RateLimiter {
+    pub fn new(settings: &RateLimitSettings) -> Self {
+        Self {
+            buckets: Mutex::new(Buckets::new(settings)),
+        }
+    }
+
+    /// Replaces every budget with those of `settings`, starting from full buckets.
+    pub async fn reconfigure(&self, settings: &RateLimitSettings) {
+        *self.buckets.lock().await = Buckets::new(settings);
+    }
+
+    /// Waits until sending one more email to `recipient` fits every budget
+    /// that applies to it, then consumes a token from each of them. Gives up
+    /// without consuming anything if that takes longer than `max_wait`,
+    /// returning how long it would have taken.
+    pub async fn acquire(&self, recipient: &str, max_wait: Duration) -> Result<(), Duration> {
+        let domain = domain(recipient);
+        loop {
+            let wait = {
+                let mut buckets = self.buckets.lock().await;
+                let mut applicable = buckets.applicable(&domain);
+                let now = Instant::now();
+                for bucket in applicable.iter_mut() {
+                    bucket.refill(now);
+                }
+                let wait = applicable
+                    .iter()
+                    .map(|bucket| bucket.wait_time())
+                    .max()
+                    .unwrap_or(Duration::ZERO);
+                if wait.is_zero() {
+                    for bucket in applicable.iter_mut() {
+                        bucket.tokens -= 1.0;
+                    }
+                    return Ok(());
+                }
+                if wait > max_wait {
+                    return Err(wait);
+                }
+                wait
+            };
+            tracing::debug!("Email send rate limit reached, waiting {:?}", wait);
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Gives back the tokens taken by `acquire` for an email to `recipient`
+    /// that was not sent after all.
+    pub async fn release(&self, recipient: &str) {
+        let domain = domain(recipient);
+        let mut buckets = self.buckets.lock().await;
+        for bucket in buckets.applicable(&domain) {
+            bucket.tokens = (bucket.tokens + 1.0).min(bucket.capacity);
+        }
+    }
+}
+
+fn domain(recipient: &str) -> String {
+    recipient
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.to_lowercase())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::{RateBudget, RateLimitSettings};
+    use crate::rate_limiter::RateLimiter;
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    const FOREVER: Duration = Duration::MAX;
+
+    fn limiter(per_second: Option<u32>, per_domain: &[(&str, u32)]) -> RateLimiter {
+        RateLimiter::new(&RateLimitSettings {
+            per_second,
+            per_hour: None,
+            per_domain: per_domain
+                .iter()
+                .map(|(domain, per_second)| {
+                    (
+                        domain.to_string(),
+                        RateBudget {
+                            per_second: Some(*per_second),
+                            per_hour: None,
+                        },
+                    )
+                })
+                .collect::<HashMap<_, _>>(),
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sends_within_budget_do_not_wait() {
+        let limiter = limiter(Some(3), &[]);
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire("ursula@domain.com", FOREVER).await.unwrap();
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sends_over_budget_wait_for_a_refill() {
+        let limiter = limiter(Some(2), &[]);
+        let start = Instant::now();
+        for _ in 0..4 {
+            limiter.acquire("ursula@domain.com", FOREVER).await.unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(999));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn domain_limits_only_apply_to_their_domain() {
+        let limiter = limiter(None, &[("gmail.com", 1)]);
+        let start = Instant::now();
+        for _ in 0..5 {
+            limiter.acquire("ursula@domain.com", FOREVER).await.unwrap();
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        limiter.acquire("ursula@Gmail.com", FOREVER).await.unwrap();
+        limiter.acquire("le.guin@gmail.com", FOREVER).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(999));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_reconfigured_limiter_enforces_the_new_budget() {
+        let limiter = limiter(None, &[]);
+        limiter
+            .reconfigure(&RateLimitSettings {
+                per_second: Some(1),
+                ..RateLimitSettings::default()
+            })
+            .await;
+        let start = Instant::now();
+        limiter.acquire("ursula@domain.com", FOREVER).await.unwrap();
+        limiter.acquire("ursula@domain.com", FOREVER).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(999));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn an_empty_configuration_never_waits() {
+        let limiter = RateLimiter::new(&RateLimitSettings::default());
+        let start = Instant::now();
+        for _ in 0..1000 {
+            limiter.acquire("ursula@domain.com", FOREVER).await.unwrap();
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sends_that_would_wait_too_long_give_up_without_spending_the_budget() {
+        let limiter = RateLimiter::new(&RateLimitSettings {
+            per_hour: Some(1),
+            ..RateLimitSettings::default()
+        });
+        let start = Instant::now();
+        limiter
+            .acquire("ursula@domain.com", Duration::ZERO)
+            .await
+            .unwrap();
+
+        let wait = limiter
+            .acquire("ursula@domain.com", Duration::from_secs(1))
+            .await
+            .unwrap_err();
+
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        assert!(wait > Duration::from_secs(3599));
+        limiter.release("ursula@domain.com").await;
+        limiter
+            .acquire("ursula@domain.com", Duration::ZERO)
+            .await
+            .unwrap();
+    }
+}
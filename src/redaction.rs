@@ -0,0 +1,175 @@
+use crate::configuration::RedactionPolicy;
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use std::sync::OnceLock;
+
+struct Redaction {
+    policy: RedactionPolicy,
+    /// Keys the hashes, so that they cannot be reversed by hashing every
+    /// address on a mailing list.
+    key: Secret<String>,
+}
+
+static REDACTION: OnceLock<Redaction> = OnceLock::new();
+
+/// Sets the policy for the rest of the process. Until it is set, and if it
+/// is set more than once, the first value wins. The default hashes with a
+/// random key, so its hashes only correlate within the process.
+pub fn set_policy(policy: RedactionPolicy, key: Secret<String>) {
+    let _ = REDACTION.set(Redaction { policy, key });
+}
+
+fn redaction() -> &'static Redaction {
+    REDACTION.get_or_init(|| Redaction {
+        policy: RedactionPolicy::default(),
+        key: Secret::new(
+            rand::thread_rng()
+                .sample_iter(Alphanumeric)
+                .take(32)
+                .map(char::from)
+                .collect(),
+        ),
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Email,
+    Name,
+}
+
+/// An email address or name, rendered for logs and spans according to the
+/// process-wide `RedactionPolicy`.
+pub struct Redacted<'a> {
+    kind: Kind,
+    value: &'a str,
+}
+
+pub fn email(value: &str) -> Redacted<'_> {
+    Redacted {
+        kind: Kind::Email,
+        value,
+    }
+}
+
+pub fn name(value: &str) -> Redacted<'_> {
+    Redacted {
+        kind: Kind::Name,
+        value,
+    }
+}
+
+impl Redacted<'_> {
+    fn render(&self, policy: RedactionPolicy, key: &str) -> String {
+        match policy {
+            RedactionPolicy::Hash => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())
+                    .expect("HMAC can take a key of any size");
+                // Addresses are case-insensitive in practice, hash them the same.
+                match self.kind {
+                    Kind::Email => mac.update(self.value.to_lowercase().as_bytes()),
+                    Kind::Name => mac.update(self.value.as_bytes()),
+                }
+                let digest = mac.finalize().into_bytes();
+                let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+                format!("hmac:{}", &hex[..16])
+            }
+            RedactionPolicy::Mask => {
+                let (local, domain) = match self.kind {
+                    Kind::Email => match self.value.rsplit_once('@') {
+                        Some((local, domain)) => (local, Some(domain)),
+                        None => (self.value, None),
+                    },
+                    Kind::Name => (self.value, None),
+                };
+                let first: String = local.chars().take(1).collect();
+                match domain {
+                    Some(domain) => format!("{}***@{}", first, domain),
+                    None => format!("{}***", first),
+                }
+            }
+            RedactionPolicy::Drop => "[redacted]".to_owned(),
+        }
+    }
+}
+
+impl std::fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let redaction = redaction();
+        write!(
+            f,
+            "{}",
+            self.render(redaction.policy, redaction.key.expose_secret())
+        )
+    }
+}
+
+impl std::fmt::Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::RedactionPolicy;
+    use crate::redaction::{email, name};
+
+    const KEY: &str = "redaction-key";
+
+    #[test]
+    fn hashes_are_stable_and_ignore_the_case_of_addresses() {
+        let hashed = email("Ursula@Example.com").render(RedactionPolicy::Hash, KEY);
+
+        assert_eq!(
+            hashed,
+            email("ursula@example.com").render(RedactionPolicy::Hash, KEY)
+        );
+        assert!(hashed.starts_with("hmac:"));
+        assert!(!hashed.contains("ursula"));
+        assert_eq!(21, hashed.len());
+    }
+
+    #[test]
+    fn hashes_depend_on_the_key() {
+        assert_ne!(
+            email("ursula@example.com").render(RedactionPolicy::Hash, KEY),
+            email("ursula@example.com").render(RedactionPolicy::Hash, "another-key")
+        );
+    }
+
+    #[test]
+    fn masking_keeps_the_first_character_and_the_domain() {
+        assert_eq!(
+            "u***@example.com",
+            email("ursula@example.com").render(RedactionPolicy::Mask, KEY)
+        );
+        assert_eq!(
+            "U***",
+            name("Ursula Le Guin").render(RedactionPolicy::Mask, KEY)
+        );
+        assert_eq!("***", name("").render(RedactionPolicy::Mask, KEY));
+    }
+
+    #[test]
+    fn dropped_values_are_replaced_with_a_placeholder() {
+        assert_eq!(
+            "[redacted]",
+            email("ursula@example.com").render(RedactionPolicy::Drop, KEY)
+        );
+        assert_eq!(
+            "[redacted]",
+            name("Ursula").render(RedactionPolicy::Drop, KEY)
+        );
+    }
+
+    #[test]
+    fn the_default_policy_hashes() {
+        let rendered = email("ursula@example.com").to_string();
+
+        assert!(rendered.starts_with("hmac:"));
+        assert_eq!(rendered, email("ursula@example.com").to_string());
+    }
+}
@@ -0,0 +1,119 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderMap, HeaderName, HeaderValue},
+    HttpMessage,
+};
+use std::future::Future;
+use tracing::Span;
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
+
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Client supplied ids longer than this are replaced with our own.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static CURRENT: RequestId;
+}
+
+/// The id returned to the client in `X-Request-Id`, set by `assign`.
+#[derive(Clone, Debug)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// The id the client sent, if it is usable, otherwise the one `TracingLogger`
+    /// generated and logs as `request_id`.
+    pub fn for_request(request: &ServiceRequest) -> Self {
+        match incoming(request.headers()) {
+            Some(id) => Self(id.to_owned()),
+            None => Self(
+                request
+                    .extensions()
+                    .get::<tracing_actix_web::RequestId>()
+                    .map(|id| id.to_string())
+                    .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+            ),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The `X-Request-Id` sent by the client, unless it is empty, too long or
+/// not printable ASCII.
+fn incoming(headers: &HeaderMap) -> Option<&str> {
+    let id = headers.get(X_REQUEST_ID)?.to_str().ok()?;
+    let usable = !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LENGTH
+        && id.bytes().all(|b| b.is_ascii_graphic());
+    usable.then_some(id)
+}
+
+/// Runs `handler` with `request_id` as the id of the current request.
+pub async fn scope<F: Future>(request_id: RequestId, handler: F) -> F::Output {
+    CURRENT.scope(request_id, handler).await
+}
+
+/// The id of the request being handled, if any.
+pub fn current() -> Option<RequestId> {
+    CURRENT.try_with(|id| id.clone()).ok()
+}
+
+/// Sets `X-Request-Id` on `response`.
+pub fn set_header<B>(response: &mut ServiceResponse<B>, request_id: &RequestId) {
+    if let Ok(value) = HeaderValue::from_str(request_id.as_str()) {
+        response.headers_mut().insert(X_REQUEST_ID, value);
+    }
+}
+
+/// Logs the id sent by the client as `client_request_id`, next to the
+/// `request_id` we generate: client ids are not guaranteed to be unique.
+pub struct RequestIdRootSpanBuilder;
+
+impl RootSpanBuilder for RequestIdRootSpanBuilder {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        let client_request_id = incoming(request.headers());
+        tracing_actix_web::root_span!(request, client_request_id)
+    }
+
+    fn on_request_end<B: MessageBody>(
+        span: Span,
+        outcome: &Result<ServiceResponse<B>, actix_web::Error>,
+    ) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::request_id::{incoming, X_REQUEST_ID};
+    use actix_web::http::header::{HeaderMap, HeaderValue};
+
+    fn headers(request_id: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(X_REQUEST_ID, HeaderValue::from_str(request_id).unwrap());
+        headers
+    }
+
+    #[test]
+    fn a_printable_request_id_is_honoured() {
+        assert_eq!(Some("req-42"), incoming(&headers("req-42")));
+    }
+
+    #[test]
+    fn unusable_request_ids_are_ignored() {
+        assert_eq!(None, incoming(&HeaderMap::new()));
+        assert_eq!(None, incoming(&headers("")));
+        assert_eq!(None, incoming(&headers("with space")));
+        assert_eq!(None, incoming(&headers(&"a".repeat(129))));
+    }
+}
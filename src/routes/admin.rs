@@ -0,0 +1,261 @@
+use crate::{
+    authentication::{basic_authentication, validate_credentials, AuthError},
+    email_delivery_worker::{queue_depth, replay_dead_letter},
+    error_response::{problem, INTERNAL_ERROR_MESSAGE},
+};
+use actix_web::{
+    http::{
+        header::{self, HeaderValue},
+        StatusCode,
+    },
+    web, HttpRequest, HttpResponse, ResponseError,
+};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::{error::Error, fmt::Formatter};
+use uuid::Uuid;
+
+#[derive(serde::Serialize)]
+struct DeadLetterSummary {
+    email_id: Uuid,
+    recipient: String,
+    subject: String,
+    n_attempts: i32,
+    last_error: String,
+    failed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct DeadLetter {
+    email_id: Uuid,
+    recipient: String,
+    recipient_name: Option<String>,
+    subject: String,
+    html_body: String,
+    text_body: String,
+    n_attempts: i32,
+    last_error: String,
+    created_at: DateTime<Utc>,
+    failed_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(
+    name = "List dead-lettered emails",
+    skip(pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+    )]
+pub async fn list_dead_letters(
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate(&request, &pool).await?;
+    let dead_letters = sqlx::query_as!(
+        DeadLetterSummary,
+        r#"
+        SELECT email_id, recipient, subject, n_attempts, last_error, failed_at
+        FROM email_dead_letters
+        ORDER BY failed_at DESC
+        "#,
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+    Ok(HttpResponse::Ok().json(dead_letters))
+}
+
+#[tracing::instrument(
+    name = "Inspect a dead-lettered email",
+    skip(pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+    )]
+pub async fn get_dead_letter(
+    email_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate(&request, &pool).await?;
+    let dead_letter = sqlx::query_as!(
+        DeadLetter,
+        r#"
+        SELECT
+            email_id, recipient, recipient_name, subject, html_body, text_body,
+            n_attempts, last_error, created_at, failed_at
+        FROM email_dead_letters
+        WHERE email_id = $1
+        "#,
+        email_id.into_inner()
+    )
+    .fetch_optional(pool.get_ref())
+    .await?;
+    match dead_letter {
+        Some(dead_letter) => Ok(HttpResponse::Ok().json(dead_letter)),
+        None => Ok(dead_letter_not_found()),
+    }
+}
+
+#[tracing::instrument(
+    name = "Replay a dead-lettered email",
+    skip(pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+    )]
+pub async fn replay_dead_letter_email(
+    email_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate(&request, &pool).await?;
+    if replay_dead_letter(&pool, email_id.into_inner()).await? {
+        Ok(HttpResponse::Accepted().finish())
+    } else {
+        Ok(dead_letter_not_found())
+    }
+}
+
+#[tracing::instrument(
+    name = "Discard a dead-lettered email",
+    skip(pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+    )]
+pub async fn discard_dead_letter(
+    email_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate(&request, &pool).await?;
+    let result = sqlx::query!(
+        r#"DELETE FROM email_dead_letters WHERE email_id = $1"#,
+        email_id.into_inner()
+    )
+    .execute(pool.get_ref())
+    .await?;
+    if result.rows_affected() > 0 {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Ok(dead_letter_not_found())
+    }
+}
+
+#[tracing::instrument(
+    name = "Report the email queue depth",
+    skip(pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+    )]
+pub async fn email_queue_stats(
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate(&request, &pool).await?;
+    Ok(HttpResponse::Ok().json(queue_depth(&pool).await?))
+}
+
+fn dead_letter_not_found() -> HttpResponse {
+    problem(
+        StatusCode::NOT_FOUND,
+        "dead_letter_not_found",
+        "There is no dead-lettered email with this id.",
+    )
+}
+
+async fn authenticate(request: &HttpRequest, pool: &PgPool) -> Result<(), AdminError> {
+    let credentials = basic_authentication(request.headers())?;
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    let user_id = validate_credentials(credentials, pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    Ok(())
+}
+
+fn error_chain_fmt(e: &impl Error, f: &mut Formatter<'_>) -> std::fmt::Result {
+    writeln!(f, "{}\n", e)?;
+    let mut current = e.source();
+    while let Some(cause) = current {
+        writeln!(f, "Caused by:\n\t{}", cause)?;
+        current = cause.source();
+    }
+    Ok(())
+}
+
+pub enum AdminError {
+    DatabaseError(sqlx::Error),
+    AuthError(String),
+    Unexpected(String),
+}
+
+impl std::fmt::Display for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdminError::DatabaseError(_) => write!(f, "Failed to query the email queue."),
+            AdminError::AuthError(e) => write!(f, "{}", e),
+            AdminError::Unexpected(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for AdminError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AdminError::DatabaseError(e) => Some(e),
+            AdminError::AuthError(_) | AdminError::Unexpected(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Debug for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<sqlx::Error> for AdminError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::DatabaseError(e)
+    }
+}
+
+impl From<String> for AdminError {
+    fn from(e: String) -> Self {
+        Self::AuthError(e)
+    }
+}
+
+impl From<AuthError> for AdminError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidCredentials(e) => Self::AuthError(e),
+            AuthError::DatabaseError(e) => Self::DatabaseError(e),
+            AuthError::Unexpected(e) => Self::Unexpected(e),
+        }
+    }
+}
+
+impl AdminError {
+    fn code(&self) -> &'static str {
+        match self {
+            AdminError::DatabaseError(_) => "database_error",
+            AdminError::AuthError(_) => "unauthorized",
+            AdminError::Unexpected(_) => "internal_error",
+        }
+    }
+}
+
+impl ResponseError for AdminError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            AdminError::DatabaseError(_) | AdminError::Unexpected(_) => problem(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                self.code(),
+                INTERNAL_ERROR_MESSAGE,
+            ),
+            AdminError::AuthError(_) => {
+                let mut response = problem(
+                    StatusCode::UNAUTHORIZED,
+                    self.code(),
+                    "Authentication failed.",
+                );
+                let header_value = HeaderValue::from_str(r#"Basic realm="admin""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, header_value);
+                response
+            }
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use crate::{
+    error_response::{internal_error, problem},
+    startup::ApplicationBaseUrl,
+};
+use actix_web::{
+    http::{header::ContentType, StatusCode},
+    web, HttpResponse,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// The feed only carries the most recent issues, the archive lists them all.
+const FEED_ENTRIES: i64 = 20;
+
+struct IssueSummary {
+    slug: String,
+    title: String,
+    published_at: DateTime<Utc>,
+}
+
+struct FeedEntry {
+    newsletter_issue_id: Uuid,
+    slug: String,
+    title: String,
+    html_content: String,
+    published_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "List archived newsletter issues", skip(pool))]
+pub async fn archive(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_published_issues(&pool)
+        .await
+        .map_err(|e| internal_error(e, "database_error"))?;
+
+    let mut items = String::new();
+    for issue in &issues {
+        items.push_str(&format!(
+            r#"<li><a href="/archive/{}">{}</a> <time datetime="{}">{}</time></li>"#,
+            issue.slug,
+            escape(&issue.title),
+            issue
+                .published_at
+                .to_rfc3339_opts(SecondsFormat::Secs, true),
+            issue.published_at.format("%Y-%m-%d"),
+        ));
+    }
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <link rel="alternate" type="application/atom+xml" href="/feed.xml">
+    <title>Newsletter archive</title>
+</head>
+<body>
+    <h1>Newsletter archive</h1>
+    <ul>{}</ul>
+</body>
+</html>"#,
+        items
+    );
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[tracing::instrument(name = "Show an archived newsletter issue", skip(pool))]
+pub async fn archive_issue(
+    slug: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT title, html_content, published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE slug = $1 AND published_at IS NOT NULL
+        "#,
+        slug.as_str()
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .map_err(|e| internal_error(e, "database_error"))?;
+
+    let issue = match issue {
+        Some(issue) => issue,
+        None => {
+            return Ok(problem(
+                StatusCode::NOT_FOUND,
+                "newsletter_issue_not_found",
+                "There is no newsletter issue at this address.",
+            ))
+        }
+    };
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    <p><a href="/archive">&larr; All issues</a></p>
+    <h1>{title}</h1>
+    <p><time datetime="{published_at}">{published_on}</time></p>
+    <article>{content}</article>
+</body>
+</html>"#,
+        title = escape(&issue.title),
+        published_at = issue
+            .published_at
+            .to_rfc3339_opts(SecondsFormat::Secs, true),
+        published_on = issue.published_at.format("%Y-%m-%d"),
+        content = issue.html_content,
+    );
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[tracing::instrument(name = "Render the newsletter Atom feed", skip(pool, base_url))]
+pub async fn feed(
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_feed_entries(&pool)
+        .await
+        .map_err(|e| internal_error(e, "database_error"))?;
+    let base_url = &base_url.0;
+
+    let updated = issues
+        .first()
+        .map(|issue| issue.published_at)
+        .unwrap_or_else(Utc::now);
+    let mut entries = String::new();
+    for issue in &issues {
+        let published_at = issue
+            .published_at
+            .to_rfc3339_opts(SecondsFormat::Secs, true);
+        entries.push_str(&format!(
+            r#"
+  <entry>
+    <id>urn:uuid:{id}</id>
+    <title>{title}</title>
+    <link rel="alternate" type="text/html" href="{base_url}/archive/{slug}"/>
+    <published>{published_at}</published>
+    <updated>{published_at}</updated>
+    <content type="html">{content}</content>
+  </entry>"#,
+            id = issue.newsletter_issue_id,
+            title = escape(&issue.title),
+            base_url = base_url,
+            slug = issue.slug,
+            published_at = published_at,
+            content = escape(&issue.html_content),
+        ));
+    }
+    let body = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <id>{base_url}/archive</id>
+  <title>Newsletter archive</title>
+  <link rel="alternate" type="text/html" href="{base_url}/archive"/>
+  <link rel="self" type="application/atom+xml" href="{base_url}/feed.xml"/>
+  <updated>{updated}</updated>
+  <author><name>zero2prod</name></author>{entries}
+</feed>
+"#,
+        base_url = base_url,
+        updated = updated.to_rfc3339_opts(SecondsFormat::Secs, true),
+        entries = entries,
+    );
+    Ok(HttpResponse::Ok()
+        .content_type("application/atom+xml; charset=utf-8")
+        .body(body))
+}
+
+#[tracing::instrument(name = "Get published newsletter issues", skip(pool))]
+async fn get_published_issues(pool: &PgPool) -> Result<Vec<IssueSummary>, sqlx::Error> {
+    let issues = sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT slug, title, published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE published_at IS NOT NULL
+        ORDER BY published_at DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(issues)
+}
+
+#[tracing::instrument(name = "Get the latest newsletter issues", skip(pool))]
+async fn get_feed_entries(pool: &PgPool) -> Result<Vec<FeedEntry>, sqlx::Error> {
+    let entries = sqlx::query_as!(
+        FeedEntry,
+        r#"
+        SELECT newsletter_issue_id, slug, title, html_content, published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE published_at IS NOT NULL
+        ORDER BY published_at DESC
+        LIMIT $1
+        "#,
+        FEED_ENTRIES
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(entries)
+}
+
+/// Escapes text so it can be embedded in HTML or XML markup.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -1,5 +1,176 @@
-use actix_web::{HttpRequest, HttpResponse, Responder};
+use crate::{
+    circuit_breaker::CircuitState,
+    configuration::{DependencyCheck, HealthSettings},
+    email_client::EmailClient,
+    email_delivery_worker::WorkerHeartbeat,
+    startup::MIGRATOR,
+};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use sqlx::{migrate::Migrate, PgPool};
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+/// What `/health/ready` needs besides the database pool and the email client.
+pub struct ReadinessChecks {
+    pub settings: HealthSettings,
+    pub worker_heartbeat: Arc<WorkerHeartbeat>,
+}
+
+#[derive(serde::Serialize)]
+struct EmailProviderHealth {
+    circuit_breaker: CircuitState,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Readiness {
+    Ready,
+    NotReady,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+enum DependencyState {
+    Up,
+    Down,
+}
+
+#[derive(serde::Serialize)]
+struct DependencyHealth {
+    status: DependencyState,
+    required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl DependencyHealth {
+    fn new(outcome: Result<(), String>, required: bool) -> Self {
+        match outcome {
+            Ok(()) => Self {
+                status: DependencyState::Up,
+                required,
+                error: None,
+            },
+            Err(e) => Self {
+                status: DependencyState::Down,
+                required,
+                error: Some(e),
+            },
+        }
+    }
+
+    fn fails_readiness(&self) -> bool {
+        self.required && matches!(self.status, DependencyState::Down)
+    }
+}
+
+#[derive(serde::Serialize)]
+struct ReadinessReport {
+    status: Readiness,
+    checks: BTreeMap<&'static str, DependencyHealth>,
+}
 
 pub async fn health_check(_request: HttpRequest) -> impl Responder {
     HttpResponse::Ok().finish()
 }
+
+pub async fn email_provider_health(email_client: web::Data<EmailClient>) -> impl Responder {
+    HttpResponse::Ok().json(EmailProviderHealth {
+        circuit_breaker: email_client.circuit_state(),
+    })
+}
+
+/// Whether the application can serve traffic: unlike `health_check`, this
+/// answers 503 when a required dependency is down.
+#[tracing::instrument(name = "Check readiness", skip_all)]
+pub async fn readiness(
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    readiness_checks: web::Data<ReadinessChecks>,
+) -> impl Responder {
+    let ReadinessChecks {
+        settings,
+        worker_heartbeat,
+    } = readiness_checks.get_ref();
+    let mut checks = BTreeMap::new();
+    checks.insert(
+        "database",
+        DependencyHealth::new(check_database(&pool).await, true),
+    );
+    checks.insert(
+        "migrations",
+        DependencyHealth::new(check_migrations(&pool).await, true),
+    );
+    checks.insert(
+        "worker",
+        DependencyHealth::new(check_worker(worker_heartbeat, settings), true),
+    );
+    if settings.email_provider != DependencyCheck::Skip {
+        let outcome = email_client.ping().await.map_err(|e| e.to_string());
+        checks.insert(
+            "email_provider",
+            DependencyHealth::new(outcome, settings.email_provider == DependencyCheck::Require),
+        );
+    }
+
+    let ready = !checks.values().any(DependencyHealth::fails_readiness);
+    let report = ReadinessReport {
+        status: if ready {
+            Readiness::Ready
+        } else {
+            Readiness::NotReady
+        },
+        checks,
+    };
+    if ready {
+        HttpResponse::Ok().json(report)
+    } else {
+        tracing::warn!("The application is not ready");
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+async fn check_database(pool: &PgPool) -> Result<(), String> {
+    sqlx::query("SELECT 1")
+        .execute(pool)
+        .await
+        .map(|_| ())
+        .map_err(|e| format!("Failed to query the database: {}", e))
+}
+
+/// Fails if a migration shipped with this build has not been applied.
+async fn check_migrations(pool: &PgPool) -> Result<(), String> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+    let applied: HashSet<i64> = connection
+        .list_applied_migrations()
+        .await
+        .map_err(|e| format!("Failed to list the applied migrations: {}", e))?
+        .into_iter()
+        .map(|migration| migration.version)
+        .collect();
+    let pending: Vec<String> = MIGRATOR
+        .iter()
+        .filter(|migration| migration.migration_type.is_up_migration())
+        .filter(|migration| !applied.contains(&migration.version))
+        .map(|migration| migration.version.to_string())
+        .collect();
+    if pending.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Pending migrations: {}", pending.join(", ")))
+    }
+}
+
+fn check_worker(heartbeat: &WorkerHeartbeat, settings: &HealthSettings) -> Result<(), String> {
+    match heartbeat.since_last_beat() {
+        None => Err("The delivery worker has not polled the outbox yet.".into()),
+        Some(elapsed) if elapsed > settings.worker_heartbeat_timeout() => Err(format!(
+            "The delivery worker last polled the outbox {}s ago.",
+            elapsed.as_secs()
+        )),
+        Some(_) => Ok(()),
+    }
+}
@@ -0,0 +1,15 @@
+use crate::{email_delivery_worker::queue_depth, metrics::Metrics};
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+pub async fn export_metrics(metrics: web::Data<Metrics>, pool: web::Data<PgPool>) -> HttpResponse {
+    metrics.observe_db_pool(&pool);
+    // Serve the other metrics even if the queue cannot be inspected right now.
+    match queue_depth(&pool).await {
+        Ok(depth) => metrics.observe_email_queue(&depth),
+        Err(e) => tracing::warn!("Failed to measure the email queue depth: {:?}", e),
+    }
+    HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(metrics.render())
+}
@@ -1,8 +1,18 @@
+mod admin;
+mod archive;
 mod health_check;
+mod metrics;
 mod newsletters;
+mod not_found;
 mod subscriptions;
 mod subscriptions_confirm;
+mod tracking;
+pub use admin::*;
+pub use archive::*;
 pub use health_check::*;
+pub use metrics::*;
 pub use newsletters::*;
+pub use not_found::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use tracking::*;
@@ -1,18 +1,104 @@
-use crate::{domain::SubscriberEmail, email_client::EmailClient};
-use actix_web::{http::header::HeaderMap, web, HttpRequest, HttpResponse, ResponseError};
-use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use crate::{
+    authentication::{basic_authentication, validate_credentials, AuthError},
+    domain::{IssueSlug, Mailbox, SubscriberEmail, SubscriberName},
+    email_client::{
+        Attachment, BatchEmail, BatchEmailResult, EmailClient, EmailClientError, EmailOptions,
+        MAX_ATTACHMENTS_SIZE,
+    },
+    email_delivery_worker::enqueue_email_with_options,
+    error_response::{problem, INTERNAL_ERROR_MESSAGE},
+    redaction,
+    tracking::Tracker,
+};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use chrono::Utc;
 use reqwest::{
     header::{self, HeaderValue},
     StatusCode,
 };
 use sqlx::PgPool;
-use std::{error::Error, fmt::Formatter};
+use std::{collections::HashMap, error::Error, fmt::Formatter, sync::Arc};
 use uuid::Uuid;
 
+/// Leaves room for attachments up to `MAX_ATTACHMENTS_SIZE` once
+/// base64-encoded, on top of actix-web's default limit for the rest.
+pub const MAX_NEWSLETTER_PAYLOAD_SIZE: usize =
+    MAX_ATTACHMENTS_SIZE.div_ceil(3) * 4 + 2 * 1024 * 1024;
+
 #[derive(serde::Deserialize, Debug)]
 pub struct BodyData {
     title: String,
     content: Content,
+    #[serde(default)]
+    tracking: bool,
+    /// The key of one of the configured sender identities.
+    sender: Option<String>,
+    reply_to: Option<String>,
+    tag: Option<String>,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+    #[serde(default)]
+    attachments: Vec<AttachmentData>,
+}
+
+/// An attachment whose content is base64-encoded. Attachments with a
+/// `content_id` are embedded in the HTML body instead of listed separately.
+#[derive(serde::Deserialize, Debug)]
+pub struct AttachmentData {
+    name: String,
+    content_type: String,
+    content: String,
+    content_id: Option<String>,
+}
+
+impl BodyData {
+    fn email_options(&self, email_client: &EmailClient) -> Result<EmailOptions, String> {
+        let sender = self
+            .sender
+            .as_ref()
+            .map(|key| {
+                email_client
+                    .sender_identity(key)
+                    .cloned()
+                    .ok_or_else(|| format!("There is no sender identity named '{}'.", key))
+            })
+            .transpose()?;
+        let reply_to = self
+            .reply_to
+            .clone()
+            .map(SubscriberEmail::parse)
+            .transpose()?;
+        let attachments = self
+            .attachments
+            .iter()
+            .map(|attachment| {
+                let content = base64::decode(&attachment.content).map_err(|_| {
+                    format!(
+                        "The content of attachment '{}' is not valid base64.",
+                        attachment.name
+                    )
+                })?;
+                let name = attachment.name.clone();
+                let content_type = attachment.content_type.clone();
+                Ok(match &attachment.content_id {
+                    Some(content_id) => {
+                        Attachment::inline(name, content_type, content, content_id.clone())
+                    }
+                    None => Attachment::new(name, content_type, content),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let options = EmailOptions {
+            sender,
+            reply_to,
+            tag: self.tag.clone(),
+            metadata: self.metadata.clone(),
+            attachments,
+            ..EmailOptions::default()
+        };
+        options.validate()?;
+        Ok(options)
+    }
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -22,36 +108,65 @@ pub struct Content {
 }
 
 struct ConfirmedSubscriber {
-    email: SubscriberEmail,
+    id: Uuid,
+    recipient: Mailbox,
+}
+
+#[derive(serde::Serialize)]
+struct PublishedIssue {
+    newsletter_issue_id: Uuid,
+    slug: String,
+}
+
+#[derive(serde::Serialize)]
+struct IssueStats {
+    newsletter_issue_id: Uuid,
+    recipients: i32,
+    unique_opens: i64,
+    unique_clicks: i64,
+    open_rate: f64,
+    click_rate: f64,
 }
 
 #[tracing::instrument(
     name = "Publish a newsletter issue",
-    skip(body, pool, email_client, request),
+    skip(body, pool, email_client, tracker, request),
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
     )]
 pub async fn publish_newsletter(
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
+    tracker: web::Data<Tracker>,
     request: HttpRequest,
 ) -> Result<HttpResponse, PublishError> {
     let subscribers = get_confirmed_subscribers(&pool).await?;
     let credentials = basic_authentication(request.headers())?;
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
     let user_id = validate_credentials(credentials, &pool).await?;
-    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    let options = body
+        .email_options(&email_client)
+        .map_err(PublishError::ValidationError)?;
+    let tracking_enabled = body.tracking && tracker.is_enabled();
+    let newsletter_issue_id = Uuid::new_v4();
+    let slug = IssueSlug::new(&body.title, newsletter_issue_id);
+    insert_newsletter_issue(&pool, newsletter_issue_id, &slug, &body, tracking_enabled).await?;
+    let mut emails = Vec::new();
     for subscriber in subscribers {
         match subscriber {
             Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await?
+                let html_content = if tracking_enabled {
+                    tracker.instrument_html(&body.content.html, newsletter_issue_id, subscriber.id)
+                } else {
+                    body.content.html.clone()
+                };
+                emails.push(BatchEmail {
+                    recipient: subscriber.recipient,
+                    subject: body.title.clone(),
+                    html_content,
+                    text_content: body.content.text.clone(),
+                });
             }
             Err(err) => {
                 tracing::warn!(
@@ -62,77 +177,240 @@ pub async fn publish_newsletter(
             }
         }
     }
+    let recipients = match deliver_newsletter_issue(&pool, &email_client, &emails, &options).await {
+        Ok(recipients) => recipients,
+        // Nothing went out: drop the issue, so that publishing it again does
+        // not leave an unpublished copy behind.
+        Err(e @ PublishError::SendEmailError(_)) => {
+            delete_newsletter_issue(&pool, newsletter_issue_id).await?;
+            return Err(e);
+        }
+        Err(e) => return Err(e),
+    };
+    record_recipients(&pool, newsletter_issue_id, recipients).await?;
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(HttpResponse::Ok().json(PublishedIssue {
+        newsletter_issue_id,
+        slug: slug.as_ref().to_owned(),
+    }))
 }
 
-#[derive(Debug)]
-struct Credentials {
-    username: String,
-    password: String,
+/// Sends the issue to every recipient, reporting how many it reached or
+/// queued for another attempt. Fails only if nothing went out because of a
+/// problem worth retrying, e.g. the provider being down: once some emails are
+/// sent, publishing again would send them twice. Instead, the emails that
+/// failed for such a problem are written to the outbox, for the delivery
+/// worker to retry.
+#[tracing::instrument(
+    name = "Deliver a newsletter issue",
+    skip(pool, email_client, emails, options)
+)]
+async fn deliver_newsletter_issue(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    emails: &[BatchEmail],
+    options: &EmailOptions,
+) -> Result<i32, PublishError> {
+    let results = if email_client.batch_enabled() {
+        email_client.send_batch(emails, options).await
+    } else {
+        let mut results = Vec::with_capacity(emails.len());
+        for email in emails {
+            let outcome = email_client
+                .send_email_with_options(
+                    &email.recipient,
+                    &email.subject,
+                    &email.html_content,
+                    &email.text_content,
+                    options,
+                )
+                .await;
+            results.push(BatchEmailResult {
+                recipient: email.recipient.email().as_ref().to_owned(),
+                outcome: outcome.map_err(Arc::new),
+            });
+        }
+        results
+    };
+
+    let mut recipients = 0;
+    let mut transient_error = None;
+    let mut retries = Vec::new();
+    // The results follow the order of `emails`.
+    for (email, result) in emails.iter().zip(results) {
+        match result.outcome {
+            Ok(()) => recipients += 1,
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to deliver a newsletter issue to {}. {}",
+                    redaction::email(&result.recipient),
+                    e
+                );
+                if e.is_transient() {
+                    transient_error.get_or_insert(e);
+                    retries.push(email);
+                }
+            }
+        }
+    }
+    if let Some(e) = transient_error {
+        if recipients == 0 {
+            return Err(e.into());
+        }
+    }
+    if !retries.is_empty() {
+        let mut transaction = pool.begin().await?;
+        for email in &retries {
+            enqueue_email_with_options(
+                &mut transaction,
+                &email.recipient,
+                &email.subject,
+                &email.html_content,
+                &email.text_content,
+                options,
+            )
+            .await?;
+        }
+        transaction.commit().await?;
+        tracing::info!(
+            "Queued {} emails of a newsletter issue for another attempt.",
+            retries.len()
+        );
+    }
+    Ok(recipients + retries.len() as i32)
 }
 
-async fn validate_credentials(
-    credentials: Credentials,
-    pool: &PgPool,
-) -> Result<Uuid, PublishError> {
+#[tracing::instrument(
+    name = "Get newsletter issue stats",
+    skip(pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+    )]
+pub async fn newsletter_issue_stats(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let credentials = basic_authentication(request.headers())?;
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    let user_id = validate_credentials(credentials, &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
     let row = sqlx::query!(
         r#"
-        SELECT user_id, password_hash
-        FROM users
-        WHERE username = $1
+        SELECT
+            recipients,
+            (SELECT COUNT(DISTINCT subscriber_id) FROM newsletter_issue_events
+             WHERE newsletter_issue_id = $1 AND kind = 'open') AS "unique_opens!",
+            (SELECT COUNT(DISTINCT subscriber_id) FROM newsletter_issue_events
+             WHERE newsletter_issue_id = $1 AND kind = 'click') AS "unique_clicks!"
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
         "#,
-        credentials.username,
+        newsletter_issue_id
     )
-    .fetch_optional(pool)
-    .await
-    .map_err(PublishError::GetSubscriberError)?;
+    .fetch_optional(pool.get_ref())
+    .await?;
 
-    let (expected_password_hash, user_id) = match row {
-        Some(row) => (row.password_hash, row.user_id),
+    let row = match row {
+        Some(row) => row,
         None => {
-            return Err(PublishError::AuthError("Unknown Username".to_string()));
+            return Ok(problem(
+                StatusCode::NOT_FOUND,
+                "newsletter_issue_not_found",
+                "There is no newsletter issue with this id.",
+            ))
         }
     };
-
-    let expected_password_hash = PasswordHash::new(&expected_password_hash)
-        .map_err(|err| PublishError::Unexpected(err.to_string()))?;
-
-    let _ = Argon2::default()
-        .verify_password(credentials.password.as_bytes(), &expected_password_hash)
-        .map_err(|_err| PublishError::AuthError("Invalid password".to_string()))?;
-
-    Ok(user_id)
+    let rate = |count: i64| {
+        if row.recipients > 0 {
+            count as f64 / row.recipients as f64
+        } else {
+            0.0
+        }
+    };
+    Ok(HttpResponse::Ok().json(IssueStats {
+        newsletter_issue_id,
+        recipients: row.recipients,
+        unique_opens: row.unique_opens,
+        unique_clicks: row.unique_clicks,
+        open_rate: rate(row.unique_opens),
+        click_rate: rate(row.unique_clicks),
+    }))
 }
 
-fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, String> {
-    let header_value = headers
-        .get("Authorization")
-        .ok_or("The 'Authorization' header was missing")?
-        .to_str()
-        .map_err(|_err| "The 'Authorization' header was not a valid UTF8 string.".to_string())?;
-
-    let base64encoded_segment = header_value
-        .strip_prefix("Basic ")
-        .ok_or("The authorization scheme was not 'Basic'.")?;
-    let decoded_bytes = base64::decode_config(base64encoded_segment, base64::STANDARD)
-        .map_err(|_err| "Failed to base64-decode 'Basic' credentials.".to_string())?;
-
-    let decoded_credentials = String::from_utf8(decoded_bytes)
-        .map_err(|_err| "The decoded credential string is not valid UTF8.".to_string())?;
-
-    let mut credentials = decoded_credentials.splitn(2, ":");
+#[tracing::instrument(
+    name = "Save newsletter issue details in the database",
+    skip(pool, body)
+)]
+async fn insert_newsletter_issue(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    slug: &IssueSlug,
+    body: &BodyData,
+    tracking_enabled: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            slug,
+            title,
+            text_content,
+            html_content,
+            tracking_enabled
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        newsletter_issue_id,
+        slug.as_ref(),
+        body.title,
+        body.content.text,
+        body.content.html,
+        tracking_enabled
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
 
-    let username = credentials
-        .next()
-        .ok_or_else(|| "A username must be provided in 'Basic' auth.".to_string())?
-        .to_string();
-    let password = credentials
-        .next()
-        .ok_or_else(|| "A password must be provided in 'Basic' auth.".to_string())?
-        .to_string();
+#[tracing::instrument(name = "Delete an undelivered newsletter issue", skip(pool))]
+async fn delete_newsletter_issue(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
 
-    Ok(Credentials { username, password })
+/// Also publishes the issue: it only shows up in the archive once delivered.
+#[tracing::instrument(
+    name = "Record the number of recipients of a newsletter issue",
+    skip(pool)
+)]
+async fn record_recipients(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    recipients: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET recipients = $2, published_at = $3
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        recipients,
+        Utc::now()
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
 }
 
 async fn get_confirmed_subscribers(
@@ -140,7 +418,7 @@ async fn get_confirmed_subscribers(
 ) -> Result<Vec<Result<ConfirmedSubscriber, String>>, sqlx::Error> {
     let rows = sqlx::query!(
         r#"
-    SELECT email
+    SELECT id, email, name
     FROM subscriptions
     WHERE status = 'confirmed'
     "#,
@@ -151,7 +429,14 @@ async fn get_confirmed_subscribers(
     let confirmed_subs = rows
         .into_iter()
         .map(|r| match SubscriberEmail::parse(r.email) {
-            Ok(email) => Ok(ConfirmedSubscriber { email }),
+            Ok(email) => {
+                // Fall back to the bare address rather than skip the subscriber.
+                let name = SubscriberName::parse(r.name).ok();
+                Ok(ConfirmedSubscriber {
+                    id: r.id,
+                    recipient: Mailbox::new(email, name),
+                })
+            }
             Err(error) => Err(error),
         })
         .collect();
@@ -170,8 +455,9 @@ fn error_chain_fmt(e: &impl Error, f: &mut Formatter<'_>) -> std::fmt::Result {
 }
 
 pub enum PublishError {
+    ValidationError(String),
     GetSubscriberError(sqlx::Error),
-    SendEmailError(reqwest::Error),
+    SendEmailError(Arc<EmailClientError>),
     AuthError(String),
     Unexpected(String),
 }
@@ -179,6 +465,7 @@ pub enum PublishError {
 impl std::fmt::Display for PublishError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            PublishError::ValidationError(e) => write!(f, "{}", e),
             PublishError::GetSubscriberError(_) => {
                 write!(f, "Failed to get subscribers in the database.")
             }
@@ -195,7 +482,8 @@ impl Error for PublishError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             PublishError::GetSubscriberError(e) => Some(e),
-            PublishError::SendEmailError(e) => Some(e),
+            PublishError::SendEmailError(e) => Some(e.as_ref()),
+            PublishError::ValidationError(_) => None,
             PublishError::AuthError(_) => None,
             PublishError::Unexpected(_) => None,
         }
@@ -213,8 +501,8 @@ impl From<sqlx::Error> for PublishError {
         Self::GetSubscriberError(value)
     }
 }
-impl From<reqwest::Error> for PublishError {
-    fn from(value: reqwest::Error) -> Self {
+impl From<Arc<EmailClientError>> for PublishError {
+    fn from(value: Arc<EmailClientError>) -> Self {
         Self::SendEmailError(value)
     }
 }
@@ -225,14 +513,63 @@ impl From<String> for PublishError {
     }
 }
 
+impl From<AuthError> for PublishError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidCredentials(e) => Self::AuthError(e),
+            AuthError::DatabaseError(e) => Self::GetSubscriberError(e),
+            AuthError::Unexpected(e) => Self::Unexpected(e),
+        }
+    }
+}
+
+impl PublishError {
+    fn code(&self) -> &'static str {
+        match self {
+            PublishError::ValidationError(_) => "invalid_newsletter",
+            PublishError::GetSubscriberError(_) => "database_error",
+            PublishError::SendEmailError(e) => match **e {
+                EmailClientError::BudgetExhausted { .. } => "send_budget_exhausted",
+                _ => "email_delivery_failed",
+            },
+            PublishError::AuthError(_) => "unauthorized",
+            PublishError::Unexpected(_) => "internal_error",
+        }
+    }
+}
+
 impl ResponseError for PublishError {
     fn error_response(&self) -> HttpResponse {
+        if let PublishError::SendEmailError(e) = self {
+            if let EmailClientError::BudgetExhausted { retry_after } = **e {
+                let mut response = problem(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    self.code(),
+                    "The email send budget is exhausted, publish the issue again later.",
+                );
+                response.headers_mut().insert(
+                    header::RETRY_AFTER,
+                    HeaderValue::from(retry_after.as_secs().max(1)),
+                );
+                return response;
+            }
+        }
         match self {
+            PublishError::ValidationError(e) => problem(StatusCode::BAD_REQUEST, self.code(), e),
             PublishError::GetSubscriberError(_)
             | PublishError::SendEmailError(_)
-            | PublishError::Unexpected(_) => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
+            | PublishError::Unexpected(_) => problem(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                self.code(),
+                INTERNAL_ERROR_MESSAGE,
+            ),
             PublishError::AuthError(_) => {
-                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                // The reason stays in the logs, it tells usernames apart.
+                let mut response = problem(
+                    StatusCode::UNAUTHORIZED,
+                    self.code(),
+                    "Authentication failed.",
+                );
                 let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
                 response
                     .headers_mut()
@@ -0,0 +1,11 @@
+use crate::error_response::problem;
+use actix_web::{http::StatusCode, HttpResponse};
+
+/// Answers the requests no route matched.
+pub async fn not_found() -> HttpResponse {
+    problem(
+        StatusCode::NOT_FOUND,
+        "not_found",
+        "There is nothing at this address.",
+    )
+}
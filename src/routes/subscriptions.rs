@@ -4,8 +4,11 @@ use std::{
 };
 
 use crate::{
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
-    email_client::EmailClient,
+    domain::{Mailbox, NewSubscriber, SubscriberEmail, SubscriberName},
+    email_delivery_worker::enqueue_email,
+    error_response::{problem, INTERNAL_ERROR_MESSAGE},
+    metrics::{Metrics, SubscriptionEvent},
+    redaction,
     startup::ApplicationBaseUrl,
 };
 use actix_web::{web, HttpResponse, ResponseError};
@@ -30,10 +33,7 @@ impl TryFrom<FormData> for NewSubscriber {
     fn try_from(value: FormData) -> Result<Self, Self::Error> {
         let name = SubscriberName::parse(value.name)?;
         let email = SubscriberEmail::parse(value.email)?;
-        Ok(NewSubscriber {
-            email: email,
-            name: name,
-        })
+        Ok(NewSubscriber { email, name })
     }
 }
 
@@ -46,19 +46,19 @@ pub struct FormData {
 
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(form, pool, base_url, metrics),
     fields(
-    subscriber_email = %form.email,
-    subscriber_name= %form.name
+    subscriber_email = %redaction::email(&form.email),
+    subscriber_name = %redaction::name(&form.name)
     )
 )]
 pub async fn subscribe(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
+    metrics: web::Data<Metrics>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into()?;
+    let new_subscriber: NewSubscriber = form.0.try_into()?;
     let mut transaction = pool
         .begin()
         .await
@@ -68,17 +68,25 @@ pub async fn subscribe(
         .map_err(SubscribeError::InsertSubscriberError)?;
     let subscription_token = generate_subscription_token();
     store_token(&mut transaction, subscriber_id, &subscription_token).await?;
-    send_confirmation_email(
-        &email_client,
-        new_subscriber,
-        &base_url.0,
-        &subscription_token,
+    let (html_body, plain_body) = confirmation_email_body(&base_url.0, &subscription_token);
+    let recipient = Mailbox::new(
+        new_subscriber.email.clone(),
+        Some(new_subscriber.name.clone()),
+    );
+    enqueue_email(
+        &mut transaction,
+        &recipient,
+        CONFIRMATION_EMAIL_SUBJECT,
+        &html_body,
+        &plain_body,
     )
-    .await?;
+    .await
+    .map_err(SubscribeError::EnqueueEmailError)?;
     transaction
         .commit()
         .await
         .map_err(SubscribeError::TransactionCommitError)?;
+    metrics.record_subscription(SubscriptionEvent::Created);
     Ok(HttpResponse::Ok().finish())
 }
 
@@ -100,7 +108,7 @@ pub async fn store_token(
     .execute(&mut **transaction)
     .await
     .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
+        tracing::error!("Failed to execute query: {}", e);
         StoreTokenError(e)
     })?;
     Ok(())
@@ -128,23 +136,18 @@ pub async fn insert_subscriber(
     .execute(&mut **transaction)
     .await
     .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
+        // The details of a database error can quote the values, e.g. the
+        // email address of a duplicate subscriber: only log the message.
+        tracing::error!("Failed to execute query: {}", e);
         e
     })?;
 
     Ok(subscriber_id)
 }
 
-#[tracing::instrument(
-    name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber, base_url, subscription_token)
-)]
-pub async fn send_confirmation_email(
-    email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
-    base_url: &str,
-    subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+const CONFIRMATION_EMAIL_SUBJECT: &str = "Welcome!";
+
+fn confirmation_email_body(base_url: &str, subscription_token: &str) -> (String, String) {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
@@ -158,9 +161,7 @@ pub async fn send_confirmation_email(
     Click <a href=\"{}\">here</a> to confirm your subscription.",
         confirmation_link
     );
-    email_client
-        .send_email(new_subscriber.email, "Welcome!", &html_body, &plain_body)
-        .await
+    (html_body, plain_body)
 }
 
 fn error_chain_fmt(e: &impl Error, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -183,7 +184,10 @@ impl std::fmt::Debug for StoreTokenError {
 
 impl Display for StoreTokenError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}\nCaused by:\n\t{}", self, self.0)
+        write!(
+            f,
+            "A database error was encountered while trying to store a subscription token."
+        )
     }
 }
 
@@ -193,12 +197,20 @@ impl Error for StoreTokenError {
     }
 }
 
-impl ResponseError for StoreTokenError {}
+impl ResponseError for StoreTokenError {
+    fn error_response(&self) -> HttpResponse {
+        problem(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "store_token_failed",
+            INTERNAL_ERROR_MESSAGE,
+        )
+    }
+}
 
 pub enum SubscribeError {
     ValidationError(String),
     StoreTokenError(StoreTokenError),
-    SendEmailError(reqwest::Error),
+    EnqueueEmailError(sqlx::Error),
     PoolError(sqlx::Error),
     InsertSubscriberError(sqlx::Error),
     TransactionCommitError(sqlx::Error),
@@ -219,8 +231,8 @@ impl std::fmt::Display for SubscribeError {
                 f,
                 "Failed to store the confirmation token for a new subscriber."
             ),
-            SubscribeError::SendEmailError(_) => {
-                write!(f, "Failed to send a confirmation email.")
+            SubscribeError::EnqueueEmailError(_) => {
+                write!(f, "Failed to store the confirmation email in the outbox.")
             }
             SubscribeError::PoolError(_) => {
                 write!(f, "Failed to acquire a Postgres connection from the pool")
@@ -242,7 +254,7 @@ impl Error for SubscribeError {
         match self {
             SubscribeError::ValidationError(_) => None,
             SubscribeError::StoreTokenError(e) => Some(e),
-            SubscribeError::SendEmailError(e) => Some(e),
+            SubscribeError::EnqueueEmailError(e) => Some(e),
             SubscribeError::PoolError(e) => Some(e),
             SubscribeError::InsertSubscriberError(e) => Some(e),
             SubscribeError::TransactionCommitError(e) => Some(e),
@@ -250,6 +262,19 @@ impl Error for SubscribeError {
     }
 }
 
+impl SubscribeError {
+    fn code(&self) -> &'static str {
+        match self {
+            SubscribeError::ValidationError(_) => "invalid_subscriber",
+            SubscribeError::StoreTokenError(_) => "store_token_failed",
+            SubscribeError::EnqueueEmailError(_) => "enqueue_email_failed",
+            SubscribeError::PoolError(_) => "database_unavailable",
+            SubscribeError::InsertSubscriberError(_) => "store_subscriber_failed",
+            SubscribeError::TransactionCommitError(_) => "transaction_failed",
+        }
+    }
+}
+
 impl ResponseError for SubscribeError {
     fn status_code(&self) -> StatusCode {
         match self {
@@ -258,14 +283,16 @@ impl ResponseError for SubscribeError {
             | SubscribeError::TransactionCommitError(_)
             | SubscribeError::InsertSubscriberError(_)
             | SubscribeError::StoreTokenError(_)
-            | SubscribeError::SendEmailError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            | SubscribeError::EnqueueEmailError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
-}
 
-impl From<reqwest::Error> for SubscribeError {
-    fn from(value: reqwest::Error) -> Self {
-        Self::SendEmailError(value)
+    fn error_response(&self) -> HttpResponse {
+        let message = match self {
+            SubscribeError::ValidationError(e) => e.as_str(),
+            _ => INTERNAL_ERROR_MESSAGE,
+        };
+        problem(self.status_code(), self.code(), message)
     }
 }
 
@@ -1,5 +1,10 @@
-use actix_web::{web, HttpResponse, Responder};
+use crate::{
+    error_response::{problem, INTERNAL_ERROR_MESSAGE},
+    metrics::{Metrics, SubscriptionEvent},
+};
+use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
 use sqlx::PgPool;
+use std::{error::Error, fmt::Formatter};
 use uuid::Uuid;
 
 #[derive(serde::Deserialize, Debug)]
@@ -7,31 +12,27 @@ pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(pool, parameters))]
+#[tracing::instrument(name = "Confirm a pending subscriber", skip(pool, parameters, metrics))]
 pub async fn confirm(
     pool: web::Data<PgPool>,
     parameters: web::Query<Parameters>,
-) -> impl Responder {
-    let id = match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
-        Ok(id) => id,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
-    };
-
-    match id {
-        Some(subscriber_id) => {
-            if confirm_subscriber(&pool, subscriber_id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
-            }
-            HttpResponse::Ok().finish()
-        }
-        None => HttpResponse::Unauthorized().finish(),
+    metrics: web::Data<Metrics>,
+) -> Result<HttpResponse, ConfirmError> {
+    let subscriber_id = get_subscriber_id_from_token(&pool, &parameters.subscription_token)
+        .await?
+        .ok_or(ConfirmError::UnknownToken)?;
+    if confirm_subscriber(&pool, subscriber_id).await? {
+        metrics.record_subscription(SubscriptionEvent::Confirmed);
     }
+    Ok(HttpResponse::Ok().finish())
 }
 
+/// Returns whether the subscriber was pending, so that clicking the link
+/// again is not counted as another confirmation.
 #[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
+pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1 AND status <> 'confirmed'"#,
         subscriber_id,
     )
     .execute(pool)
@@ -40,7 +41,7 @@ pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<()
         tracing::error!("Failed to execute query: {:?}", e);
         e
     })?;
-    Ok(())
+    Ok(result.rows_affected() == 1)
 }
 
 #[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
@@ -60,3 +61,67 @@ pub async fn get_subscriber_id_from_token(
     })?;
     Ok(result.map(|val| val.subscriber_id))
 }
+
+fn error_chain_fmt(e: &impl Error, f: &mut Formatter<'_>) -> std::fmt::Result {
+    writeln!(f, "{}\n", e)?;
+    let mut current = e.source();
+    while let Some(cause) = current {
+        writeln!(f, "Caused by:\n\t{}", cause)?;
+        current = cause.source();
+    }
+    Ok(())
+}
+
+pub enum ConfirmError {
+    UnknownToken,
+    DatabaseError(sqlx::Error),
+}
+
+impl std::fmt::Display for ConfirmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfirmError::UnknownToken => {
+                write!(f, "There is no subscriber with this subscription token.")
+            }
+            ConfirmError::DatabaseError(_) => write!(f, "Failed to confirm the subscriber."),
+        }
+    }
+}
+
+impl Error for ConfirmError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfirmError::UnknownToken => None,
+            ConfirmError::DatabaseError(e) => Some(e),
+        }
+    }
+}
+
+impl std::fmt::Debug for ConfirmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<sqlx::Error> for ConfirmError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::DatabaseError(e)
+    }
+}
+
+impl ResponseError for ConfirmError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ConfirmError::UnknownToken => problem(
+                StatusCode::UNAUTHORIZED,
+                "unknown_subscription_token",
+                &self.to_string(),
+            ),
+            ConfirmError::DatabaseError(_) => problem(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "database_error",
+                INTERNAL_ERROR_MESSAGE,
+            ),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use crate::{
+    error_response::problem,
+    tracking::{Tracker, TrackingEvent, TRACKING_PIXEL},
+};
+use actix_web::{
+    http::{header, StatusCode},
+    web, HttpResponse,
+};
+use chrono::Utc;
+use sqlx::PgPool;
+
+#[tracing::instrument(name = "Track a newsletter open", skip(token, pool, tracker))]
+pub async fn track_open(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    tracker: web::Data<Tracker>,
+) -> HttpResponse {
+    match tracker.verify(&token) {
+        Ok(event @ TrackingEvent::Open { .. }) => {
+            // A failure to record the event must not break the reader's email client.
+            if tracker.is_enabled() {
+                let _ = store_tracking_event(&pool, &event).await;
+            }
+        }
+        Ok(_) | Err(_) => tracing::warn!("Received an invalid open-tracking token."),
+    }
+    HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .body(TRACKING_PIXEL)
+}
+
+#[tracing::instrument(name = "Track a newsletter click", skip(token, pool, tracker))]
+pub async fn track_click(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    tracker: web::Data<Tracker>,
+) -> HttpResponse {
+    let event = match tracker.verify(&token) {
+        Ok(event) => event,
+        Err(e) => {
+            tracing::warn!("Received an invalid click-tracking token: {}", e);
+            return invalid_click_token();
+        }
+    };
+    let url = match &event {
+        TrackingEvent::Click { url, .. } => url.clone(),
+        TrackingEvent::Open { .. } => return invalid_click_token(),
+    };
+    // Readers must land on the link even if we fail to record the click, or
+    // tracking has been switched off since the issue went out.
+    if tracker.is_enabled() {
+        let _ = store_tracking_event(&pool, &event).await;
+    }
+    HttpResponse::Found()
+        .insert_header((header::LOCATION, url))
+        .finish()
+}
+
+fn invalid_click_token() -> HttpResponse {
+    problem(
+        StatusCode::BAD_REQUEST,
+        "invalid_tracking_token",
+        "The link is invalid or has been tampered with.",
+    )
+}
+
+#[tracing::instrument(name = "Store a tracking event in the database", skip(pool))]
+async fn store_tracking_event(pool: &PgPool, event: &TrackingEvent) -> Result<(), sqlx::Error> {
+    let (newsletter_issue_id, subscriber_id, url) = match event {
+        TrackingEvent::Open {
+            newsletter_issue_id,
+            subscriber_id,
+        } => (newsletter_issue_id, subscriber_id, None),
+        TrackingEvent::Click {
+            newsletter_issue_id,
+            subscriber_id,
+            url,
+        } => (newsletter_issue_id, subscriber_id, Some(url)),
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_events (newsletter_issue_id, subscriber_id, kind, url, occurred_at)
+        SELECT $1, $2, $3, $4, $5
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND tracking_enabled
+        "#,
+        newsletter_issue_id,
+        subscriber_id,
+        event.kind(),
+        url,
+        Utc::now()
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
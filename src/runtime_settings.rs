@@ -0,0 +1,234 @@
+use crate::{
+    configuration::{get_configuration, RateLimitSettings, Settings},
+    email_client::EmailClient,
+    telemetry::LogFilterHandle,
+    tracking::Tracker,
+};
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::EnvFilter;
+
+/// The subset of `Settings` that can change without restarting the application.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeSettings {
+    /// `log.filter` with the `log.modules` levels, see `LogSettings::directives`.
+    pub log_filter: String,
+    pub email_rate_limit: RateLimitSettings,
+    pub email_batch_enabled: bool,
+    pub tracking_enabled: bool,
+}
+
+impl From<&Settings> for RuntimeSettings {
+    fn from(settings: &Settings) -> Self {
+        Self {
+            log_filter: settings.log.directives(),
+            email_rate_limit: settings.email_client.rate_limit.clone(),
+            email_batch_enabled: settings.email_client.batch_enabled,
+            tracking_enabled: settings.tracking.enabled,
+        }
+    }
+}
+
+impl RuntimeSettings {
+    /// The names of the settings that differ between `self` and `other`.
+    fn changes(&self, other: &RuntimeSettings) -> Vec<&'static str> {
+        let mut changes = Vec::new();
+        if self.log_filter != other.log_filter {
+            changes.push("log.filter");
+        }
+        if self.email_rate_limit != other.email_rate_limit {
+            changes.push("email_client.rate_limit");
+        }
+        if self.email_batch_enabled != other.email_batch_enabled {
+            changes.push("email_client.batch_enabled");
+        }
+        if self.tracking_enabled != other.tracking_enabled {
+            changes.push("tracking.enabled");
+        }
+        changes
+    }
+}
+
+/// Applies new `RuntimeSettings` to the components of a running application.
+#[derive(Clone)]
+pub struct RuntimeReloader {
+    current: Arc<Mutex<RuntimeSettings>>,
+    email_client: Arc<EmailClient>,
+    tracker: Arc<Tracker>,
+    log_filter: Option<LogFilterHandle>,
+}
+
+impl RuntimeReloader {
+    pub fn new(
+        settings: RuntimeSettings,
+        email_client: Arc<EmailClient>,
+        tracker: Arc<Tracker>,
+    ) -> Self {
+        Self {
+            current: Arc::new(Mutex::new(settings)),
+            email_client,
+            tracker,
+            log_filter: None,
+        }
+    }
+
+    /// Lets reloads swap the log filter. Without a handle `log.filter` is
+    /// still validated but has no effect.
+    pub fn with_log_filter(mut self, handle: LogFilterHandle) -> Self {
+        self.log_filter = Some(handle);
+        self
+    }
+
+    pub fn current(&self) -> RuntimeSettings {
+        self.current.lock().unwrap().clone()
+    }
+
+    /// Swaps in `settings`, returning the names of the settings that changed.
+    /// Nothing is applied if any of them is invalid.
+    pub async fn apply(&self, settings: RuntimeSettings) -> Result<Vec<&'static str>, String> {
+        let filter = EnvFilter::try_new(&settings.log_filter)
+            .map_err(|e| format!("log.filter is not a valid filter: {}", e))?;
+        let changes = self.current().changes(&settings);
+
+        if changes.contains(&"log.filter") {
+            if let Some(handle) = &self.log_filter {
+                handle.set(filter)?;
+            }
+        }
+        // Reconfiguring refills the buckets, so only do it for a new budget.
+        if changes.contains(&"email_client.rate_limit") {
+            self.email_client
+                .set_rate_limit(&settings.email_rate_limit)
+                .await;
+        }
+        self.email_client
+            .set_batch_enabled(settings.email_batch_enabled);
+        self.tracker.set_enabled(settings.tracking_enabled);
+        *self.current.lock().unwrap() = settings;
+        Ok(changes)
+    }
+
+    /// Re-reads the configuration and applies its runtime subset, keeping the
+    /// current settings if the configuration is unreadable or invalid.
+    #[tracing::instrument(name = "Reload the runtime settings", skip(self))]
+    pub async fn reload(&self) -> Result<Vec<&'static str>, String> {
+        let outcome = self.try_reload().await;
+        match &outcome {
+            Ok(changes) if changes.is_empty() => {
+                tracing::info!("Reloaded the runtime settings, nothing changed")
+            }
+            Ok(changes) => tracing::info!(
+                changed = %changes.join(", "),
+                "Reloaded the runtime settings"
+            ),
+            Err(e) => tracing::error!(
+                error.message = %e,
+                "Failed to reload the runtime settings, keeping the current ones"
+            ),
+        }
+        outcome
+    }
+
+    async fn try_reload(&self) -> Result<Vec<&'static str>, String> {
+        let configuration =
+            get_configuration().map_err(|e| format!("Failed to read the configuration: {}", e))?;
+        configuration.validate().map_err(|e| e.to_string())?;
+        self.apply(RuntimeSettings::from(&configuration)).await
+    }
+}
+
+/// Reloads the runtime settings every time the process receives `SIGHUP`.
+pub async fn reload_on_sighup(reloader: RuntimeReloader) -> Result<(), std::io::Error> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangups = signal(SignalKind::hangup())?;
+        while hangups.recv().await.is_some() {
+            tracing::info!("Received SIGHUP");
+            let _ = reloader.reload().await;
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = reloader;
+        std::future::pending().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::{get_configuration_for, RateLimitSettings};
+    use crate::runtime_settings::{RuntimeReloader, RuntimeSettings};
+    use crate::tracking::Tracker;
+    use std::sync::Arc;
+
+    fn reloader() -> (RuntimeReloader, RuntimeSettings) {
+        let configuration = get_configuration_for("test").unwrap();
+        let settings = RuntimeSettings::from(&configuration);
+        let email_client = Arc::new(configuration.email_client.client().unwrap());
+        let tracker = Arc::new(Tracker::new(
+            configuration.application.base_url,
+            configuration.tracking,
+        ));
+        (
+            RuntimeReloader::new(settings.clone(), email_client, tracker),
+            settings,
+        )
+    }
+
+    #[tokio::test]
+    async fn applying_the_same_settings_changes_nothing() {
+        let (reloader, settings) = reloader();
+        assert_eq!(reloader.apply(settings).await, Ok(vec![]));
+    }
+
+    #[tokio::test]
+    async fn applied_settings_reach_the_running_components() {
+        let (reloader, settings) = reloader();
+        let updated = RuntimeSettings {
+            log_filter: "debug".to_owned(),
+            email_rate_limit: RateLimitSettings {
+                per_second: Some(1),
+                ..RateLimitSettings::default()
+            },
+            email_batch_enabled: !settings.email_batch_enabled,
+            tracking_enabled: !settings.tracking_enabled,
+        };
+
+        let changes = reloader.apply(updated.clone()).await.unwrap();
+
+        assert_eq!(
+            changes,
+            vec![
+                "log.filter",
+                "email_client.rate_limit",
+                "email_client.batch_enabled",
+                "tracking.enabled"
+            ]
+        );
+        assert_eq!(reloader.current(), updated);
+        assert_eq!(
+            reloader.email_client.batch_enabled(),
+            updated.email_batch_enabled
+        );
+        assert_eq!(reloader.tracker.is_enabled(), updated.tracking_enabled);
+    }
+
+    #[tokio::test]
+    async fn an_invalid_log_filter_is_rejected_without_applying_anything() {
+        let (reloader, settings) = reloader();
+        let updated = RuntimeSettings {
+            log_filter: "info,[".to_owned(),
+            email_batch_enabled: !settings.email_batch_enabled,
+            ..settings.clone()
+        };
+
+        assert!(reloader.apply(updated).await.is_err());
+        assert_eq!(reloader.current(), settings);
+        assert_eq!(
+            reloader.email_client.batch_enabled(),
+            settings.email_batch_enabled
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use tokio::sync::watch;
+
+/// Tells the background tasks of the application to wind down.
+pub struct Shutdown(watch::Sender<bool>);
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self(watch::channel(false).0)
+    }
+
+    pub fn trigger(&self) {
+        self.0.send_replace(true);
+    }
+
+    pub fn subscribe(&self) -> ShutdownSignal {
+        ShutdownSignal(self.0.subscribe())
+    }
+}
+
+/// Held by a task that should stop when its `Shutdown` is triggered.
+#[derive(Clone)]
+pub struct ShutdownSignal(watch::Receiver<bool>);
+
+impl ShutdownSignal {
+    pub fn is_triggered(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once shutdown is triggered, or once the `Shutdown` is dropped
+    /// since nothing could trigger it anymore.
+    pub async fn triggered(&mut self) {
+        let _ = self.0.wait_for(|triggered| *triggered).await;
+    }
+}
+
+/// Resolves when the process receives `SIGTERM` or `SIGINT`.
+pub async fn wait_for_termination() -> Result<(), std::io::Error> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            _ = terminate.recv() => tracing::info!("Received SIGTERM"),
+            o = tokio::signal::ctrl_c() => {
+                o?;
+                tracing::info!("Received SIGINT")
+            }
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shutdown::Shutdown;
+
+    #[tokio::test]
+    async fn every_signal_sees_the_shutdown() {
+        let shutdown = Shutdown::new();
+        let mut first = shutdown.subscribe();
+        let mut second = first.clone();
+        assert!(!first.is_triggered());
+
+        shutdown.trigger();
+
+        first.triggered().await;
+        second.triggered().await;
+        assert!(second.is_triggered());
+    }
+
+    #[tokio::test]
+    async fn a_signal_subscribed_after_the_shutdown_sees_it() {
+        let shutdown = Shutdown::new();
+        shutdown.trigger();
+
+        let mut signal = shutdown.subscribe();
+
+        assert!(signal.is_triggered());
+        signal.triggered().await;
+    }
+}
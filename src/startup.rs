@@ -1,56 +1,126 @@
 use crate::{
-    configuration::{DatabaseSettings, Settings},
+    configuration::{ApplicationSettings, DatabaseSettings, Settings},
     email_client::EmailClient,
-    routes::{confirm, health_check, publish_newsletter, subscribe},
+    email_delivery_worker::WorkerHeartbeat,
+    error_response::extractor_error,
+    metrics::Metrics,
+    request_id::{self, RequestId, RequestIdRootSpanBuilder},
+    routes::{
+        archive, archive_issue, confirm, discard_dead_letter, email_provider_health,
+        email_queue_stats, export_metrics, feed, get_dead_letter, health_check, list_dead_letters,
+        newsletter_issue_stats, not_found, publish_newsletter, readiness, replay_dead_letter_email,
+        subscribe, track_click, track_open, ReadinessChecks, MAX_NEWSLETTER_PAYLOAD_SIZE,
+    },
+    runtime_settings::{RuntimeReloader, RuntimeSettings},
+    tracking::Tracker,
+};
+use actix_web::{
+    dev::{Server, ServerHandle, Service},
+    web, App, HttpServer,
+};
+use sqlx::{
+    migrate::{MigrateError, Migrator},
+    PgPool,
 };
-use actix_web::{dev::Server, web, App, HttpServer};
-use sqlx::PgPool;
 use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Instant;
 use tracing_actix_web::TracingLogger;
 
+/// The migrations this build expects the database to be at.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
 pub struct Application {
     port: u16,
     server: Server,
+    email_client: Arc<EmailClient>,
+    worker_heartbeat: Arc<WorkerHeartbeat>,
+    runtime_reloader: RuntimeReloader,
 }
 
 pub struct ApplicationBaseUrl(pub String);
 
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
+        configuration
+            .validate()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
         let connection_pool = get_connection_pool(&configuration.database);
-        let sender_email = configuration
+        if configuration.application.run_migrations {
+            run_migrations(&connection_pool)
+                .await
+                .map_err(std::io::Error::other)?;
+        }
+        let metrics = Arc::new(Metrics::new());
+        let email_client = configuration
             .email_client
-            .sender()
-            .expect("Invalid sender email address.");
-
-        let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
-        );
+            .clone()
+            .client()
+            .map(|client| Arc::new(client.with_metrics(metrics.clone())))
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
 
         let address = format!(
             "{}:{}",
             configuration.application.host, configuration.application.port
         );
 
+        let runtime_settings = RuntimeSettings::from(&configuration);
+        let tracker = Arc::new(Tracker::new(
+            configuration.application.base_url.clone(),
+            configuration.tracking,
+        ));
+        let runtime_reloader =
+            RuntimeReloader::new(runtime_settings, email_client.clone(), tracker.clone());
+        let worker_heartbeat = Arc::new(WorkerHeartbeat::default());
+
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
         let server = run(
             listener,
             connection_pool,
-            email_client,
-            configuration.application.base_url,
+            email_client.clone(),
+            tracker,
+            metrics,
+            ReadinessChecks {
+                settings: configuration.health,
+                worker_heartbeat: worker_heartbeat.clone(),
+            },
+            &configuration.application,
         )?;
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            email_client,
+            worker_heartbeat,
+            runtime_reloader,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    /// The client used by the API, to be shared with the delivery worker so
+    /// that both draw from the same rate limits and circuit breaker.
+    pub fn email_client(&self) -> Arc<EmailClient> {
+        self.email_client.clone()
+    }
+
+    /// To be beaten by the delivery worker, `/health/ready` reports it.
+    pub fn worker_heartbeat(&self) -> Arc<WorkerHeartbeat> {
+        self.worker_heartbeat.clone()
+    }
+
+    pub fn runtime_reloader(&self) -> RuntimeReloader {
+        self.runtime_reloader.clone()
+    }
+
+    /// Stops the server. It does not listen for signals itself, the caller
+    /// decides when to shut down.
+    pub fn server_handle(&self) -> ServerHandle {
+        self.server.handle()
+    }
+
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
         self.server.await
     }
@@ -59,30 +129,125 @@ impl Application {
 pub fn run(
     listener: TcpListener,
     db_pool: PgPool,
-    email_client: EmailClient,
-    base_url: String,
+    email_client: Arc<EmailClient>,
+    tracker: Arc<Tracker>,
+    metrics: Arc<Metrics>,
+    readiness_checks: ReadinessChecks,
+    settings: &ApplicationSettings,
 ) -> Result<Server, std::io::Error> {
     let db_pool = web::Data::new(db_pool);
-    let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let email_client = web::Data::from(email_client);
+    let tracker = web::Data::from(tracker);
+    let metrics = web::Data::from(metrics);
+    let readiness_checks = web::Data::new(readiness_checks);
+    let base_url = web::Data::new(ApplicationBaseUrl(settings.base_url.clone()));
     let server = HttpServer::new(move || {
+        let request_metrics = metrics.clone();
         App::new()
-            .wrap(TracingLogger::default())
+            .wrap_fn(move |request, service| {
+                let metrics = request_metrics.clone();
+                let started_at = Instant::now();
+                let response = service.call(request);
+                async move {
+                    let response = response.await?;
+                    let request = response.request();
+                    metrics.record_http_request(
+                        request.method().as_str(),
+                        request.match_pattern().as_deref().unwrap_or("unmatched"),
+                        response.status().as_u16(),
+                        started_at.elapsed(),
+                    );
+                    Ok(response)
+                }
+            })
+            .wrap_fn(|request, service| {
+                let request_id = RequestId::for_request(&request);
+                let response = request_id::scope(request_id.clone(), service.call(request));
+                async move {
+                    let mut response = response.await?;
+                    request_id::set_header(&mut response, &request_id);
+                    Ok(response)
+                }
+            })
+            .wrap(TracingLogger::<RequestIdRootSpanBuilder>::new())
             .route("/health_check", web::get().to(health_check))
+            .route(
+                "/health_check/email_provider",
+                web::get().to(email_provider_health),
+            )
+            .route("/health/ready", web::get().to(readiness))
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
-            .route("/newsletters", web::post().to(publish_newsletter))
+            .service(
+                web::resource("/newsletters")
+                    .app_data(
+                        web::JsonConfig::default()
+                            .limit(MAX_NEWSLETTER_PAYLOAD_SIZE)
+                            .error_handler(extractor_error("invalid_json")),
+                    )
+                    .route(web::post().to(publish_newsletter)),
+            )
+            .route(
+                "/newsletters/{newsletter_issue_id}/stats",
+                web::get().to(newsletter_issue_stats),
+            )
+            .route("/archive", web::get().to(archive))
+            .route("/archive/{slug}", web::get().to(archive_issue))
+            .route("/feed.xml", web::get().to(feed))
+            .route("/metrics", web::get().to(export_metrics))
+            .route("/t/o/{token}", web::get().to(track_open))
+            .route("/t/c/{token}", web::get().to(track_click))
+            .route("/admin/email_queue", web::get().to(email_queue_stats))
+            .route("/admin/dead_letters", web::get().to(list_dead_letters))
+            .route(
+                "/admin/dead_letters/{email_id}",
+                web::get().to(get_dead_letter),
+            )
+            .route(
+                "/admin/dead_letters/{email_id}",
+                web::delete().to(discard_dead_letter),
+            )
+            .route(
+                "/admin/dead_letters/{email_id}/replay",
+                web::post().to(replay_dead_letter_email),
+            )
+            .default_service(web::to(not_found))
+            .app_data(web::QueryConfig::default().error_handler(extractor_error("invalid_query")))
+            .app_data(web::FormConfig::default().error_handler(extractor_error("invalid_form")))
+            .app_data(web::JsonConfig::default().error_handler(extractor_error("invalid_json")))
+            .app_data(web::PathConfig::default().error_handler(extractor_error("invalid_path")))
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
+            .app_data(tracker.clone())
+            .app_data(metrics.clone())
+            .app_data(readiness_checks.clone())
             .app_data(base_url.clone())
     })
+    .disable_signals()
+    .shutdown_timeout(settings.shutdown_timeout_seconds)
     .listen(listener)?
     .run();
     Ok(server)
 }
 
 pub fn get_connection_pool(db_settings: &DatabaseSettings) -> PgPool {
-    let dsn = db_settings.connection_string();
-    let connection_pool = PgPool::connect_lazy(&dsn).expect("Failed to connect to Postgres.");
-    connection_pool
+    db_settings
+        .pool_options()
+        .connect_lazy_with(db_settings.with_db())
+}
+
+/// Applies the pending migrations of `MIGRATOR`. The migrator holds a Postgres
+/// advisory lock meanwhile, so instances starting together apply them once.
+pub async fn run_migrations(pool: &PgPool) -> Result<(), String> {
+    match MIGRATOR.run(pool).await {
+        Ok(()) => {
+            tracing::info!("The database schema is up to date");
+            Ok(())
+        }
+        Err(MigrateError::VersionMissing(version)) => Err(format!(
+            "The database schema is ahead of this build: migration {} is unknown.",
+            version
+        )),
+        Err(e) => Err(format!("Failed to migrate the database: {}", e)),
+    }
 }
@@ -1,18 +1,122 @@
-use tracing::{subscriber::set_global_default, Subscriber};
+use crate::configuration::{LogFileSettings, LogFormat, LogRotation, LogSettings, OtlpSettings};
+use opentelemetry::{global, propagation::Injector, trace::TraceError, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::Tracer, Resource};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::collections::HashMap;
+use tracing::{subscriber::set_global_default, Span, Subscriber};
+use tracing_appender::rolling::{InitError, RollingFileAppender, Rotation};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
-use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
+use tracing_logfmt::{EventsFormatter, FieldsFormatter};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    fmt::{self, MakeWriter},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    reload, EnvFilter, Layer, Registry,
+};
 
-pub fn get_subscriber<F>(name: &str, filter: &str, sink: F) -> impl Subscriber + Send + Sync
+const TRACEPARENT: &str = "traceparent";
+
+/// Swaps the filter of a subscriber built by `get_subscriber` while it is installed.
+#[derive(Clone)]
+pub struct LogFilterHandle(reload::Handle<EnvFilter, Registry>);
+
+impl LogFilterHandle {
+    pub fn set(&self, filter: EnvFilter) -> Result<(), String> {
+        self.0
+            .reload(filter)
+            .map_err(|e| format!("Failed to swap the log filter: {}", e))
+    }
+}
+
+/// Builds a tracer exporting spans to the OTLP collector of `settings`.
+/// The exporter runs on its own thread, since actix runs a current-thread runtime.
+pub fn otlp_tracer(name: &str, settings: &OtlpSettings) -> Result<Tracer, TraceError> {
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(&settings.endpoint)
+                .with_timeout(settings.timeout()),
+        )
+        .with_trace_config(
+            opentelemetry_sdk::trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                name.to_owned(),
+            )])),
+        )
+        .install_batch(opentelemetry_sdk::runtime::TokioCurrentThread)
+}
+
+/// Logs to `sink`, and to a rotating file if `settings` configure one, in the
+/// configured format. Spans are also exported through `tracer`, when there is one.
+pub fn get_subscriber<F>(
+    name: &str,
+    settings: &LogSettings,
+    sink: F,
+    tracer: Option<Tracer>,
+) -> Result<(impl Subscriber + Send + Sync, LogFilterHandle), InitError>
 where
     F: for<'a> MakeWriter<'a> + Send + Sync + 'static,
 {
-    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(filter));
-    let formatting_layer = BunyanFormattingLayer::new(name.to_owned(), sink);
-    Registry::default()
+    let env_filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(settings.directives()));
+    let (env_filter, handle) = reload::Layer::new(env_filter);
+    let file_layer = match &settings.file {
+        Some(file) => Some(formatting_layer(
+            name,
+            settings.format,
+            log_file(file)?,
+            false,
+        )),
+        None => None,
+    };
+    let subscriber = Registry::default()
         .with(env_filter)
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)))
         .with(JsonStorageLayer)
-        .with(formatting_layer)
+        .with(formatting_layer(name, settings.format, sink, true))
+        .with(file_layer);
+    Ok((subscriber, LogFilterHandle(handle)))
+}
+
+fn formatting_layer<S, W>(
+    name: &str,
+    format: LogFormat,
+    writer: W,
+    ansi: bool,
+) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Bunyan => Box::new(BunyanFormattingLayer::new(name.to_owned(), writer)),
+        LogFormat::Pretty => Box::new(fmt::layer().pretty().with_ansi(ansi).with_writer(writer)),
+        LogFormat::Logfmt => Box::new(
+            fmt::layer()
+                .event_format(EventsFormatter::default())
+                .fmt_fields(FieldsFormatter::default())
+                .with_ansi(false)
+                .with_writer(writer),
+        ),
+    }
+}
+
+fn log_file(settings: &LogFileSettings) -> Result<RollingFileAppender, InitError> {
+    let rotation = match settings.rotation {
+        LogRotation::Minutely => Rotation::MINUTELY,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Never => Rotation::NEVER,
+    };
+    RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(&settings.file_name_prefix)
+        .build(&settings.directory)
 }
 
 pub fn init_subscriber<T>(subscriber: T)
@@ -20,5 +124,179 @@ where
     T: Subscriber + Send + Sync,
 {
     LogTracer::init().expect("Failed to set logger");
+    global::set_text_map_propagator(TraceContextPropagator::new());
     set_global_default(subscriber).expect("Failed to set subscriber");
 }
+
+/// The W3C `traceparent` of the current span, if it belongs to a trace.
+pub fn current_traceparent() -> Option<String> {
+    let mut carrier = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&Span::current().context(), &mut carrier)
+    });
+    carrier.remove(TRACEPARENT)
+}
+
+/// Makes `span` a child of the span that produced `traceparent`.
+pub fn set_parent_from_traceparent(span: &Span, traceparent: &str) {
+    let carrier = HashMap::from([(TRACEPARENT.to_owned(), traceparent.to_owned())]);
+    let context = global::get_text_map_propagator(|propagator| propagator.extract(&carrier));
+    span.set_parent(context);
+}
+
+/// Headers carrying the trace context of the current span to another service.
+pub fn trace_context_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(
+            &Span::current().context(),
+            &mut HeaderInjector(&mut headers),
+        )
+    });
+    headers
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::{LogFileSettings, LogFormat, LogRotation, LogSettings};
+    use crate::telemetry::{
+        current_traceparent, get_subscriber, set_parent_from_traceparent, trace_context_headers,
+    };
+    use opentelemetry::{global, trace::TracerProvider as _};
+    use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::TracerProvider};
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Logs one event with `settings`, returning what was written to stdout.
+    fn log_with(settings: &LogSettings) -> String {
+        let buffer = Buffer::default();
+        let sink = buffer.clone();
+        let (subscriber, _) = get_subscriber("test", settings, move || sink.clone(), None)
+            .expect("Failed to build the subscriber.");
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(subscriber_count = 42, "Published a newsletter issue");
+        });
+        let output = buffer.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn settings(format: LogFormat) -> LogSettings {
+        LogSettings {
+            format,
+            ..LogSettings::default()
+        }
+    }
+
+    #[test]
+    fn bunyan_logs_are_json() {
+        let output = log_with(&settings(LogFormat::Bunyan));
+
+        let line: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!("Published a newsletter issue", line["msg"]);
+        assert_eq!(42, line["subscriber_count"]);
+    }
+
+    #[test]
+    fn logfmt_logs_are_key_value_pairs() {
+        let output = log_with(&settings(LogFormat::Logfmt));
+
+        assert!(output.contains("level=info"), "{}", output);
+        assert!(output.contains("subscriber_count=42"), "{}", output);
+    }
+
+    #[test]
+    fn pretty_logs_are_readable() {
+        let output = log_with(&settings(LogFormat::Pretty));
+
+        assert!(
+            output.contains("Published a newsletter issue"),
+            "{}",
+            output
+        );
+        assert!(output.contains("subscriber_count"), "{}", output);
+    }
+
+    #[test]
+    fn module_levels_silence_noisy_modules() {
+        let mut settings = settings(LogFormat::Logfmt);
+        settings
+            .modules
+            .insert("zero2prod::telemetry".into(), "warn".into());
+
+        assert_eq!("", log_with(&settings));
+    }
+
+    #[test]
+    fn logs_are_also_written_to_the_configured_file() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let settings = LogSettings {
+            file: Some(LogFileSettings {
+                directory: directory.to_str().unwrap().to_owned(),
+                file_name_prefix: "zero2prod.log".into(),
+                rotation: LogRotation::Never,
+            }),
+            ..settings(LogFormat::Logfmt)
+        };
+
+        log_with(&settings);
+
+        let file = std::fs::read_to_string(directory.join("zero2prod.log")).unwrap();
+        assert!(file.contains("subscriber_count=42"), "{}", file);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn an_incoming_trace_is_continued_in_outgoing_requests() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let provider = TracerProvider::builder().build();
+        let subscriber = Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let incoming = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("Handle a request");
+            set_parent_from_traceparent(&span, incoming);
+            let _guard = span.enter();
+
+            let outgoing = current_traceparent().expect("No trace context was injected.");
+            assert!(outgoing.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+            assert_ne!(incoming, outgoing);
+            assert_eq!(outgoing, trace_context_headers()["traceparent"]);
+        });
+    }
+
+    #[test]
+    fn there_is_no_trace_context_outside_of_a_trace() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+
+        assert_eq!(None, current_traceparent());
+        assert!(trace_context_headers().is_empty());
+    }
+}
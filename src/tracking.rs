@@ -0,0 +1,332 @@
+use crate::configuration::TrackingSettings;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use std::sync::atomic::{AtomicBool, Ordering};
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A 1x1 transparent GIF served by the open-tracking endpoint.
+pub const TRACKING_PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+#[derive(Debug, PartialEq)]
+pub enum TrackingEvent {
+    Open {
+        newsletter_issue_id: Uuid,
+        subscriber_id: Uuid,
+    },
+    Click {
+        newsletter_issue_id: Uuid,
+        subscriber_id: Uuid,
+        url: String,
+    },
+}
+
+impl TrackingEvent {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TrackingEvent::Open { .. } => "open",
+            TrackingEvent::Click { .. } => "click",
+        }
+    }
+
+    fn to_payload(&self) -> String {
+        match self {
+            TrackingEvent::Open {
+                newsletter_issue_id,
+                subscriber_id,
+            } => format!("o|{}|{}", newsletter_issue_id, subscriber_id),
+            TrackingEvent::Click {
+                newsletter_issue_id,
+                subscriber_id,
+                url,
+            } => format!("c|{}|{}|{}", newsletter_issue_id, subscriber_id, url),
+        }
+    }
+
+    fn from_payload(payload: &str) -> Option<Self> {
+        let mut parts = payload.splitn(4, '|');
+        let kind = parts.next()?;
+        let newsletter_issue_id = Uuid::parse_str(parts.next()?).ok()?;
+        let subscriber_id = Uuid::parse_str(parts.next()?).ok()?;
+        match (kind, parts.next()) {
+            ("o", None) => Some(TrackingEvent::Open {
+                newsletter_issue_id,
+                subscriber_id,
+            }),
+            ("c", Some(url)) => Some(TrackingEvent::Click {
+                newsletter_issue_id,
+                subscriber_id,
+                url: url.to_string(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+pub struct Tracker {
+    base_url: String,
+    signing_key: Secret<String>,
+    enabled: AtomicBool,
+}
+
+impl Tracker {
+    pub fn new(base_url: String, settings: TrackingSettings) -> Self {
+        Self {
+            base_url,
+            signing_key: settings.signing_key,
+            enabled: AtomicBool::new(settings.enabled),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn sign(&self, event: &TrackingEvent) -> String {
+        let payload = event.to_payload();
+        let signature = self.mac(payload.as_bytes()).finalize().into_bytes();
+        format!(
+            "{}.{}",
+            base64::encode_config(payload, base64::URL_SAFE_NO_PAD),
+            base64::encode_config(signature, base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    pub fn verify(&self, token: &str) -> Result<TrackingEvent, String> {
+        let (encoded_payload, encoded_signature) = token
+            .split_once('.')
+            .ok_or_else(|| "The tracking token is malformed.".to_string())?;
+        let payload = base64::decode_config(encoded_payload, base64::URL_SAFE_NO_PAD)
+            .map_err(|_| "Failed to base64-decode the tracking token payload.".to_string())?;
+        let signature = base64::decode_config(encoded_signature, base64::URL_SAFE_NO_PAD)
+            .map_err(|_| "Failed to base64-decode the tracking token signature.".to_string())?;
+        self.mac(&payload)
+            .verify_slice(&signature)
+            .map_err(|_| "The tracking token signature is invalid.".to_string())?;
+        let payload = String::from_utf8(payload)
+            .map_err(|_| "The tracking token payload is not valid UTF8.".to_string())?;
+        TrackingEvent::from_payload(&payload)
+            .ok_or_else(|| "The tracking token payload is malformed.".to_string())
+    }
+
+    /// Rewrites every `http(s)` link in `html` through the click redirect and
+    /// adds the open-tracking pixel for a single recipient, at the end of the
+    /// `<body>` if there is one.
+    pub fn instrument_html(
+        &self,
+        html: &str,
+        newsletter_issue_id: Uuid,
+        subscriber_id: Uuid,
+    ) -> String {
+        let mut instrumented = String::with_capacity(html.len());
+        let mut rest = html;
+        while let Some((value_start, quote)) = find_href(rest) {
+            let Some(value_len) = rest[value_start..].find(quote) else {
+                break;
+            };
+            let url = &rest[value_start..value_start + value_len];
+            instrumented.push_str(&rest[..value_start]);
+            if url.starts_with("http://") || url.starts_with("https://") {
+                let event = TrackingEvent::Click {
+                    newsletter_issue_id,
+                    subscriber_id,
+                    url: url.replace("&amp;", "&"),
+                };
+                instrumented.push_str(&format!("{}/t/c/{}", self.base_url, self.sign(&event)));
+            } else {
+                instrumented.push_str(url);
+            }
+            rest = &rest[value_start + value_len..];
+        }
+        instrumented.push_str(rest);
+
+        let pixel = TrackingEvent::Open {
+            newsletter_issue_id,
+            subscriber_id,
+        };
+        let pixel = format!(
+            r#"<img src="{}/t/o/{}" width="1" height="1" alt="" />"#,
+            self.base_url,
+            self.sign(&pixel)
+        );
+        match instrumented.to_ascii_lowercase().rfind("</body>") {
+            Some(body_end) => instrumented.insert_str(body_end, &pixel),
+            None => instrumented.push_str(&pixel),
+        }
+        instrumented
+    }
+
+    fn mac(&self, payload: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(self.signing_key.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size");
+        mac.update(payload);
+        mac
+    }
+}
+
+const HREF: &[u8] = b"href";
+
+/// Finds the next quoted `href` attribute, in any case and with whitespace
+/// around the `=`, and returns where its value starts and the quote that
+/// ends it. Attributes that only end in `href`, like `data-href`, are skipped.
+fn find_href(html: &str) -> Option<(usize, char)> {
+    let bytes = html.as_bytes();
+    let skip_whitespace = |i: usize| {
+        i + bytes[i..]
+            .iter()
+            .take_while(|b| b.is_ascii_whitespace())
+            .count()
+    };
+    let mut offset = 0;
+    while let Some(position) = bytes[offset..]
+        .windows(HREF.len())
+        .position(|window| window.eq_ignore_ascii_case(HREF))
+    {
+        let start = offset + position;
+        offset = start + HREF.len();
+        if start == 0 || !bytes[start - 1].is_ascii_whitespace() {
+            continue;
+        }
+        let equals = skip_whitespace(offset);
+        if bytes.get(equals) != Some(&b'=') {
+            continue;
+        }
+        let quote = skip_whitespace(equals + 1);
+        if let Some(&quote_char @ (b'"' | b'\'')) = bytes.get(quote) {
+            return Some((quote + 1, quote_char as char));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::TrackingSettings;
+    use crate::tracking::{Tracker, TrackingEvent};
+    use claim::{assert_err, assert_ok};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn tracker() -> Tracker {
+        Tracker::new(
+            "http://127.0.0.1".to_string(),
+            TrackingSettings {
+                enabled: true,
+                signing_key: Secret::new("a-very-secret-key".to_string()),
+            },
+        )
+    }
+
+    fn click() -> TrackingEvent {
+        TrackingEvent::Click {
+            newsletter_issue_id: Uuid::new_v4(),
+            subscriber_id: Uuid::new_v4(),
+            url: "https://example.com/?a=1|2".to_string(),
+        }
+    }
+
+    #[test]
+    fn a_signed_token_is_verified_successfully() {
+        let tracker = tracker();
+        let event = click();
+        let token = tracker.sign(&event);
+        assert_eq!(tracker.verify(&token), Ok(event));
+    }
+
+    #[test]
+    fn a_tampered_token_is_rejected() {
+        let tracker = tracker();
+        let token = tracker.sign(&click());
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = TrackingEvent::Click {
+            newsletter_issue_id: Uuid::new_v4(),
+            subscriber_id: Uuid::new_v4(),
+            url: "https://evil.example.com".to_string(),
+        };
+        let forged_payload = tracker.sign(&forged);
+        let (payload, _) = forged_payload.split_once('.').unwrap();
+        assert_err!(tracker.verify(&format!("{}.{}", payload, signature)));
+    }
+
+    #[test]
+    fn a_token_signed_with_another_key_is_rejected() {
+        let other = Tracker::new(
+            "http://127.0.0.1".to_string(),
+            TrackingSettings {
+                enabled: true,
+                signing_key: Secret::new("another-key".to_string()),
+            },
+        );
+        let token = other.sign(&click());
+        assert_err!(tracker().verify(&token));
+    }
+
+    #[test]
+    fn http_links_are_rewritten_and_a_pixel_is_appended() {
+        let tracker = tracker();
+        let html = r#"<p><a href="https://example.com/?a=1&amp;b=2">x</a> <a href='mailto:a@b.c'>y</a></p>"#;
+        let instrumented = tracker.instrument_html(html, Uuid::new_v4(), Uuid::new_v4());
+
+        assert!(!instrumented.contains("https://example.com"));
+        assert!(instrumented.contains(r#"<a href="http://127.0.0.1/t/c/"#));
+        assert!(instrumented.contains("href='mailto:a@b.c'"));
+        assert!(instrumented.contains(r#"<img src="http://127.0.0.1/t/o/"#));
+
+        let token = instrumented
+            .split("/t/c/")
+            .nth(1)
+            .and_then(|s| s.split('"').next())
+            .unwrap();
+        let event = assert_ok!(tracker.verify(token));
+        match event {
+            TrackingEvent::Click { url, .. } => assert_eq!(url, "https://example.com/?a=1&b=2"),
+            TrackingEvent::Open { .. } => panic!("Expected a click event"),
+        }
+    }
+
+    #[test]
+    fn href_attributes_are_found_in_any_case() {
+        let html = r#"<a HREF="https://example.com/">x</a>"#;
+        let instrumented = tracker().instrument_html(html, Uuid::new_v4(), Uuid::new_v4());
+
+        assert!(!instrumented.contains("https://example.com"));
+        assert!(instrumented.contains(r#"<a HREF="http://127.0.0.1/t/c/"#));
+    }
+
+    #[test]
+    fn href_attributes_may_have_whitespace_around_the_equals_sign() {
+        let html = r#"<a href = "https://example.com/">x</a>"#;
+        let instrumented = tracker().instrument_html(html, Uuid::new_v4(), Uuid::new_v4());
+
+        assert!(!instrumented.contains("https://example.com"));
+        assert!(instrumented.contains(r#"<a href = "http://127.0.0.1/t/c/"#));
+    }
+
+    #[test]
+    fn attributes_ending_in_href_are_left_alone() {
+        let html = r#"<div data-href="https://example.com/">x</div>"#;
+        let instrumented = tracker().instrument_html(html, Uuid::new_v4(), Uuid::new_v4());
+
+        assert!(instrumented.starts_with(html));
+    }
+
+    #[test]
+    fn the_pixel_is_placed_inside_the_body() {
+        let html = "<html><BODY><p>Hello</p></BODY></html>";
+        let instrumented = tracker().instrument_html(html, Uuid::new_v4(), Uuid::new_v4());
+
+        let pixel = instrumented.find("<img").unwrap();
+        assert!(pixel < instrumented.find("</BODY>").unwrap());
+        assert!(instrumented.ends_with("</BODY></html>"));
+    }
+}
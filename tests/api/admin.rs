@@ -0,0 +1,163 @@
+use crate::helpers::{spawn_app_with, TestApp};
+use reqwest::{Client, Method};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Subscribes someone while the email provider is down, so that their
+/// confirmation email is dead-lettered on its first and only attempt.
+async fn create_dead_letter(app: &TestApp) -> String {
+    let _guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let dead_letters: serde_json::Value = app
+        .admin_request(Method::GET, "dead_letters")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    dead_letters[0]["email_id"].as_str().unwrap().to_owned()
+}
+
+async fn queue_stats(app: &TestApp) -> serde_json::Value {
+    app.admin_request(Method::GET, "email_queue")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+#[actix_rt::test]
+async fn admin_endpoints_reject_anonymous_requests() {
+    let app = spawn_app_with(|c| c.email_outbox.max_attempts = 1).await;
+
+    for path in ["email_queue", "dead_letters"] {
+        let response = Client::new()
+            .get(format!("{}/admin/{}", &app.address, path))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        assert_eq!(401, response.status().as_u16());
+        assert_eq!(
+            r#"Basic realm="admin""#,
+            response.headers()["WWW-Authenticate"]
+        );
+    }
+}
+
+#[actix_rt::test]
+async fn dead_letters_can_be_listed_and_inspected() {
+    let app = spawn_app_with(|c| c.email_outbox.max_attempts = 1).await;
+    let email_id = create_dead_letter(&app).await;
+
+    let response = app
+        .admin_request(Method::GET, &format!("dead_letters/{}", email_id))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    let dead_letter: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("ursula_le_guin@gmail.com", dead_letter["recipient"]);
+    assert_eq!(1, dead_letter["n_attempts"]);
+    assert!(dead_letter["html_body"]
+        .as_str()
+        .unwrap()
+        .contains("confirm"));
+    assert!(!dead_letter["last_error"].as_str().unwrap().is_empty());
+}
+
+#[actix_rt::test]
+async fn an_unknown_dead_letter_returns_a_404() {
+    let app = spawn_app_with(|c| c.email_outbox.max_attempts = 1).await;
+    let path = format!("dead_letters/{}", uuid::Uuid::new_v4());
+
+    for method in [Method::GET, Method::DELETE] {
+        let response = app.admin_request(method, &path).send().await.unwrap();
+        assert_eq!(404, response.status().as_u16());
+    }
+    let response = app
+        .admin_request(Method::POST, &format!("{}/replay", path))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn a_replayed_dead_letter_is_delivered_again() {
+    let app = spawn_app_with(|c| c.email_outbox.max_attempts = 1).await;
+    let email_id = create_dead_letter(&app).await;
+
+    let response = app
+        .admin_request(Method::POST, &format!("dead_letters/{}/replay", email_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(202, response.status().as_u16());
+    assert_eq!(1, queue_stats(&app).await["ready"]);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let stats = queue_stats(&app).await;
+    assert_eq!(0, stats["ready"]);
+    assert_eq!(0, stats["dead_letters"]);
+}
+
+#[actix_rt::test]
+async fn a_discarded_dead_letter_is_gone() {
+    let app = spawn_app_with(|c| c.email_outbox.max_attempts = 1).await;
+    let email_id = create_dead_letter(&app).await;
+    assert_eq!(1, queue_stats(&app).await["dead_letters"]);
+
+    let response = app
+        .admin_request(Method::DELETE, &format!("dead_letters/{}", email_id))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(204, response.status().as_u16());
+    assert_eq!(0, queue_stats(&app).await["dead_letters"]);
+}
+
+#[actix_rt::test]
+async fn the_queue_stats_count_retrying_emails() {
+    let app = spawn_app_with(|c| c.email_outbox.max_attempts = 3).await;
+    // A first retry is due within a second, sooner than an admin request
+    // can get through password hashing in a debug build.
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "60"))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    assert_eq!(1, queue_stats(&app).await["ready"]);
+
+    app.dispatch_all_pending_emails().await;
+
+    let stats = queue_stats(&app).await;
+    assert_eq!(0, stats["ready"]);
+    assert_eq!(1, stats["retrying"]);
+    assert_eq!(0, stats["dead_letters"]);
+}
@@ -0,0 +1,140 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use reqwest::Client;
+use wiremock::matchers::path;
+use wiremock::{Mock, ResponseTemplate};
+
+async fn publish(app: &TestApp, title: &str, html: &str) -> String {
+    let body = serde_json::json!({
+        "title": title,
+        "content": {
+            "text": "Newsletter body as a plain text",
+            "html": html,
+        }
+    });
+    let published: serde_json::Value = app
+        .post_newsletters(body)
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    published["slug"].as_str().unwrap().to_owned()
+}
+
+async fn get(app: &TestApp, path: &str) -> reqwest::Response {
+    Client::new()
+        .get(format!("{}{}", &app.address, path))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[actix_rt::test]
+async fn the_archive_lists_published_issues() {
+    let app = spawn_app().await;
+    let first = publish(&app, "First issue", "<p>One</p>").await;
+    let second = publish(&app, "Fish & Chips", "<p>Two</p>").await;
+
+    let response = get(&app, "/archive").await;
+
+    assert_eq!(200, response.status().as_u16());
+    let html = response.text().await.unwrap();
+    assert!(html.contains(&format!(r#"href="/archive/{}""#, first)));
+    assert!(html.contains(&format!(r#"href="/archive/{}""#, second)));
+    assert!(html.contains("Fish &amp; Chips"));
+}
+
+#[actix_rt::test]
+async fn an_archived_issue_renders_its_html_body() {
+    let app = spawn_app().await;
+    let slug = publish(&app, "First issue", "<p>Newsletter body as HTML</p>").await;
+
+    let response = get(&app, &format!("/archive/{}", slug)).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let html = response.text().await.unwrap();
+    assert!(html.contains("<h1>First issue</h1>"));
+    assert!(html.contains("<p>Newsletter body as HTML</p>"));
+}
+
+#[actix_rt::test]
+async fn an_unknown_issue_returns_a_404() {
+    let app = spawn_app().await;
+
+    let response = get(&app, "/archive/does-not-exist").await;
+
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn the_feed_contains_an_entry_per_issue() {
+    let app = spawn_app().await;
+    let slug = publish(&app, "First issue", "<p>One</p>").await;
+
+    let response = get(&app, "/feed.xml").await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        "application/atom+xml; charset=utf-8",
+        response.headers()["Content-Type"]
+    );
+    let xml = response.text().await.unwrap();
+    assert!(xml.contains(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#));
+    assert_eq!(1, xml.matches("<entry>").count());
+    assert!(xml.contains("<title>First issue</title>"));
+    assert!(xml.contains(&format!("/archive/{}", slug)));
+    assert!(xml.contains("&lt;p&gt;One&lt;/p&gt;"));
+}
+
+#[actix_rt::test]
+async fn the_feed_only_contains_the_latest_issues() {
+    let app = spawn_app().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id, slug, title, text_content, html_content, tracking_enabled,
+            published_at
+        )
+        SELECT id, id::text, 'Issue ' || n, 'text', '<p>html</p>', false,
+            now() - n * interval '1 day'
+        FROM (SELECT gen_random_uuid() AS id, n FROM generate_series(1, 25) AS n) AS issues
+        "#,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let xml = get(&app, "/feed.xml").await.text().await.unwrap();
+    assert_eq!(20, xml.matches("<entry>").count());
+    assert!(xml.contains("<title>Issue 1</title>"));
+    assert!(!xml.contains("<title>Issue 21</title>"));
+
+    let html = get(&app, "/archive").await.text().await.unwrap();
+    assert_eq!(25, html.matches("<li>").count());
+}
+
+#[actix_rt::test]
+async fn issues_that_failed_to_go_out_are_not_published() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email/batch"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let body = serde_json::json!({
+        "title": "Undelivered issue",
+        "content": {
+            "text": "Newsletter body as a plain text",
+            "html": "<p>One</p>",
+        }
+    });
+    let response = app.post_newsletters(body).await;
+    assert_eq!(500, response.status().as_u16());
+
+    let html = get(&app, "/archive").await.text().await.unwrap();
+    assert!(!html.contains("Undelivered issue"));
+    let xml = get(&app, "/feed.xml").await.text().await.unwrap();
+    assert_eq!(0, xml.matches("<entry>").count());
+}
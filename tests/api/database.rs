@@ -0,0 +1,99 @@
+use crate::helpers::{create_database, spawn_app_with};
+use uuid::Uuid;
+use zero2prod::{
+    configuration::{get_configuration_for, Settings},
+    startup::{Application, MIGRATOR},
+};
+
+#[actix_rt::test]
+async fn connections_apply_the_configured_statement_timeout() {
+    let app = spawn_app_with(|c| c.database.pool.statement_timeout_milliseconds = Some(1234)).await;
+
+    let timeout: (String,) = sqlx::query_as("SHOW statement_timeout")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    assert_eq!("1234ms", timeout.0);
+}
+
+#[actix_rt::test]
+async fn statements_over_the_timeout_are_cancelled() {
+    let app = spawn_app_with(|c| c.database.pool.statement_timeout_milliseconds = Some(50)).await;
+
+    let outcome = sqlx::query("SELECT pg_sleep(1)")
+        .execute(&app.db_pool)
+        .await;
+
+    assert!(outcome.is_err());
+}
+
+fn unmigrated_configuration() -> Settings {
+    let mut configuration = get_configuration_for("test").expect("Failed to read configuration.");
+    configuration.database.database_name = Uuid::new_v4().to_string();
+    configuration.application.run_migrations = true;
+    configuration
+}
+
+#[actix_rt::test]
+async fn pending_migrations_are_applied_at_startup_when_enabled() {
+    let configuration = unmigrated_configuration();
+    let pool = create_database(&configuration.database).await;
+
+    // Instances starting together take turns applying the migrations.
+    let (first, second) = tokio::join!(
+        Application::build(configuration.clone()),
+        Application::build(configuration.clone())
+    );
+    first.expect("Failed to build the first application.");
+    second.expect("Failed to build the second application.");
+
+    let applied: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM _sqlx_migrations")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(MIGRATOR.iter().count() as i64, applied.0);
+}
+
+#[actix_rt::test]
+async fn migrations_are_not_applied_at_startup_by_default() {
+    let mut configuration = unmigrated_configuration();
+    configuration.application.run_migrations = false;
+    let pool = create_database(&configuration.database).await;
+
+    Application::build(configuration)
+        .await
+        .expect("Failed to build the application.");
+
+    let migrations_table: (Option<String>,) =
+        sqlx::query_as("SELECT to_regclass('_sqlx_migrations')::TEXT")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(None, migrations_table.0);
+}
+
+#[actix_rt::test]
+async fn the_application_refuses_to_start_on_a_schema_ahead_of_it() {
+    let configuration = unmigrated_configuration();
+    let pool = create_database(&configuration.database).await;
+    MIGRATOR.run(&pool).await.unwrap();
+    sqlx::query(
+        r#"
+        INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+        VALUES (99990101000000, 'from a newer build', true, '\x00', 0)
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let outcome = Application::build(configuration).await;
+
+    let error = outcome.err().expect("The application started.");
+    assert!(
+        error.to_string().contains("ahead of this build"),
+        "{}",
+        error
+    );
+}
@@ -1,12 +1,15 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with};
 use reqwest::Client;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::DependencyCheck;
 
 #[actix_rt::test]
 async fn test_health_check() {
     let app = spawn_app().await;
     let client = Client::new();
     let response = client
-        .get(&format!("{}/health_check", &app.address))
+        .get(format!("{}/health_check", &app.address))
         .send()
         .await
         .expect("Failed to execute request.");
@@ -14,3 +17,153 @@ async fn test_health_check() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+#[actix_rt::test]
+async fn the_email_provider_health_reports_an_open_circuit() {
+    let app = spawn_app_with(|c| c.email_client.circuit_breaker.failure_threshold = 1).await;
+    crate::helpers::create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter Title",
+            "content": {
+                "text": "Newsletter body as a plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+    assert_eq!(500, response.status().as_u16());
+
+    let health: serde_json::Value = Client::new()
+        .get(format!("{}/health_check/email_provider", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!("open", health["circuit_breaker"]);
+}
+
+async fn get_readiness(app: &crate::helpers::TestApp) -> (u16, serde_json::Value) {
+    let response = Client::new()
+        .get(format!("{}/health/ready", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let status = response.status().as_u16();
+    (status, response.json().await.unwrap())
+}
+
+#[actix_rt::test]
+async fn the_application_is_ready_when_its_dependencies_are_up() {
+    let app = spawn_app().await;
+    app.worker_heartbeat.beat();
+
+    let (status, report) = get_readiness(&app).await;
+
+    assert_eq!(200, status);
+    assert_eq!("ready", report["status"]);
+    assert_eq!("up", report["checks"]["database"]["status"]);
+    assert_eq!("up", report["checks"]["migrations"]["status"]);
+    assert_eq!("up", report["checks"]["worker"]["status"]);
+    // The email provider is not checked unless configured.
+    assert!(report["checks"]["email_provider"].is_null());
+}
+
+#[actix_rt::test]
+async fn the_application_is_not_ready_before_the_worker_polls_the_outbox() {
+    let app = spawn_app().await;
+
+    let (status, report) = get_readiness(&app).await;
+
+    assert_eq!(503, status);
+    assert_eq!("not_ready", report["status"]);
+    assert_eq!("down", report["checks"]["worker"]["status"]);
+    assert_eq!("up", report["checks"]["database"]["status"]);
+}
+
+#[actix_rt::test]
+async fn the_application_is_not_ready_when_the_worker_stops_polling() {
+    let app = spawn_app_with(|c| {
+        c.email_outbox.poll_interval_milliseconds = 1;
+        c.health.worker_heartbeat_timeout_milliseconds = 10;
+    })
+    .await;
+    app.worker_heartbeat.beat();
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+    let (status, report) = get_readiness(&app).await;
+
+    assert_eq!(503, status);
+    assert_eq!("down", report["checks"]["worker"]["status"]);
+}
+
+#[actix_rt::test]
+async fn an_unreachable_email_provider_is_reported_without_failing_readiness() {
+    let app = spawn_app_with(|c| {
+        c.email_client.base_url = "http://127.0.0.1:1".into();
+        c.health.email_provider = DependencyCheck::Report;
+    })
+    .await;
+    app.worker_heartbeat.beat();
+
+    let (status, report) = get_readiness(&app).await;
+
+    assert_eq!(200, status);
+    assert_eq!("down", report["checks"]["email_provider"]["status"]);
+    assert_eq!(false, report["checks"]["email_provider"]["required"]);
+}
+
+#[actix_rt::test]
+async fn a_required_email_provider_fails_readiness_when_unreachable() {
+    let app = spawn_app_with(|c| {
+        c.email_client.base_url = "http://127.0.0.1:1".into();
+        c.health.email_provider = DependencyCheck::Require;
+    })
+    .await;
+    app.worker_heartbeat.beat();
+
+    let (status, report) = get_readiness(&app).await;
+
+    assert_eq!(503, status);
+    assert_eq!("not_ready", report["status"]);
+    assert_eq!("down", report["checks"]["email_provider"]["status"]);
+}
+
+#[actix_rt::test]
+async fn a_reachable_email_provider_is_up() {
+    let app = spawn_app_with(|c| c.health.email_provider = DependencyCheck::Require).await;
+    app.worker_heartbeat.beat();
+
+    let (status, report) = get_readiness(&app).await;
+
+    assert_eq!(200, status);
+    assert_eq!("up", report["checks"]["email_provider"]["status"]);
+}
+
+#[actix_rt::test]
+async fn the_application_is_not_ready_with_pending_migrations() {
+    let app = spawn_app().await;
+    app.worker_heartbeat.beat();
+    sqlx::query(
+        "DELETE FROM _sqlx_migrations WHERE version = (SELECT MAX(version) FROM _sqlx_migrations)",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let (status, report) = get_readiness(&app).await;
+
+    assert_eq!(503, status);
+    assert_eq!("down", report["checks"]["migrations"]["status"]);
+    assert!(report["checks"]["migrations"]["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("Pending migrations"));
+}
@@ -1,12 +1,24 @@
+use actix_web::dev::ServerHandle;
 use argon2::{password_hash::SaltString, Argon2, PasswordHasher};
 use linkify::{LinkFinder, LinkKind};
 use reqwest::Client;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use std::io::Write;
+use std::sync::{Arc, Mutex, OnceLock};
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
 use zero2prod::{
-    configuration::{get_configuration, DatabaseSettings},
-    startup::{get_connection_pool, Application},
+    configuration::{
+        get_configuration_for, DatabaseSettings, EmailOutboxSettings, LogSettings, RedactionPolicy,
+        Settings,
+    },
+    email_client::EmailClient,
+    email_delivery_worker::{try_execute_task, ExecutionOutcome, WorkerHeartbeat},
+    redaction,
+    runtime_settings::RuntimeReloader,
+    startup::{get_connection_pool, Application, MIGRATOR},
+    telemetry::get_subscriber,
 };
 
 pub struct ConfirmationLinks {
@@ -54,13 +66,31 @@ pub struct TestApp {
     pub db_pool: PgPool,
     pub email_server: MockServer,
     pub port: u16,
+    pub email_client: Arc<EmailClient>,
+    pub email_outbox: EmailOutboxSettings,
+    pub runtime_reloader: RuntimeReloader,
+    /// The worker does not run in tests, beat it to look alive.
+    pub worker_heartbeat: Arc<WorkerHeartbeat>,
+    pub server_handle: ServerHandle,
     test_user: TestUser,
 }
 
 impl TestApp {
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_execute_task(&self.db_pool, &self.email_client, &self.email_outbox)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         Client::new()
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -83,30 +113,90 @@ impl TestApp {
             confirmation_link
         };
 
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
         ConfirmationLinks { html, plain_text }
     }
 
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
         Client::new()
-            .post(&format!("{}/newsletters", &self.address))
+            .post(format!("{}/newsletters", &self.address))
             .json(&body)
             .basic_auth(&self.test_user.username, Some(&self.test_user.password))
             .send()
             .await
             .expect("Failed to execute request.")
     }
+
+    pub async fn get_newsletter_issue_stats(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        Client::new()
+            .get(format!(
+                "{}/newsletters/{}/stats",
+                &self.address, newsletter_issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub fn admin_request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        Client::new()
+            .request(method, format!("{}/admin/{}", &self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl CapturedLogs {
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+}
+
+impl Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Starts capturing the logs of the whole test binary, with the default log
+/// settings and the `hash` redaction policy. They are shared by every test,
+/// so look for values unique to yours.
+pub fn capture_logs() -> CapturedLogs {
+    static LOGS: OnceLock<CapturedLogs> = OnceLock::new();
+    LOGS.get_or_init(|| {
+        let configuration = get_configuration_for("test").expect("Failed to read configuration.");
+        redaction::set_policy(RedactionPolicy::Hash, configuration.log.redaction_key);
+        let logs = CapturedLogs::default();
+        let sink = logs.clone();
+        let (subscriber, _) =
+            get_subscriber("test", &LogSettings::default(), move || sink.clone(), None)
+                .expect("Failed to build the subscriber.");
+        tracing::subscriber::set_global_default(subscriber).expect("Failed to set subscriber");
+        logs
+    })
+    .clone()
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+pub async fn spawn_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
     let email_server = MockServer::start().await;
 
     let configuration = {
-        let mut c = get_configuration().expect("Failed to read configuration.");
+        let mut c = get_configuration_for("test").expect("Failed to read configuration.");
         c.database.database_name = Uuid::new_v4().to_string();
-        c.application.port = 0;
         c.email_client.base_url = email_server.uri();
+        customise(&mut c);
         c
     };
 
@@ -114,14 +204,23 @@ pub async fn spawn_app() -> TestApp {
         .await
         .expect("Failed to build application.");
     let application_port = application.port();
+    let runtime_reloader = application.runtime_reloader();
+    let email_client = application.email_client();
+    let worker_heartbeat = application.worker_heartbeat();
+    let server_handle = application.server_handle();
     configure_database(&configuration.database).await;
-    let _ = tokio::spawn(application.run_until_stopped());
+    tokio::spawn(application.run_until_stopped());
 
     let app = TestApp {
         address: format!("http://localhost:{}", application_port),
         db_pool: get_connection_pool(&configuration.database),
-        email_server: email_server,
+        email_server,
         port: application_port,
+        email_client,
+        email_outbox: configuration.email_outbox.clone(),
+        runtime_reloader,
+        worker_heartbeat,
+        server_handle,
         test_user: TestUser::generate(),
     };
 
@@ -130,19 +229,66 @@ pub async fn spawn_app() -> TestApp {
 }
 
 async fn configure_database(config: &DatabaseSettings) -> PgPool {
-    let mut connection = PgConnection::connect(&config.connection_string_without_db())
+    let connection_pool = create_database(config).await;
+    MIGRATOR
+        .run(&connection_pool)
+        .await
+        .expect("Failed to migrate the database");
+    connection_pool
+}
+
+/// Creates the database of `config`, without applying any migration.
+pub async fn create_database(config: &DatabaseSettings) -> PgPool {
+    let mut connection = PgConnection::connect_with(&config.without_db())
         .await
         .expect("Failed to connect to Postgres");
     connection
         .execute(format!(r#"CREATE DATABASE "{}";"#, config.database_name).as_str())
         .await
         .expect("Failed to create database.");
-    let connection_pool = PgPool::connect(&config.connection_string())
+    PgPool::connect_with(config.with_db())
         .await
-        .expect("Failed to connect to Postgres.");
-    sqlx::migrate!("./migrations")
-        .run(&connection_pool)
+        .expect("Failed to connect to Postgres.")
+}
+
+/// A successful response from Postmark's batch endpoint for `messages` messages.
+pub fn batch_response(messages: usize) -> ResponseTemplate {
+    let results: Vec<_> = (0..messages)
+        .map(|_| serde_json::json!({"ErrorCode": 0, "Message": "OK"}))
+        .collect();
+    ResponseTemplate::new(200).set_body_json(results)
+}
+
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
         .await
-        .expect("Failed to migrate the database");
-    connection_pool
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_link = create_unconfirmed_subscriber(app).await;
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
 }
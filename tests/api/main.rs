@@ -1,5 +1,13 @@
+mod admin;
+mod archive;
+mod database;
 mod health_check;
 mod helpers;
+mod metrics;
 mod newsletter;
+mod problem_details;
+mod request_id;
+mod shutdown;
 mod subscription_confirm;
 mod subscriptions;
+mod tracking;
@@ -0,0 +1,83 @@
+use crate::helpers::{
+    create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app, TestApp,
+};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn get_metrics(app: &TestApp) -> String {
+    let response = reqwest::get(format!("{}/metrics", &app.address))
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+    response.text().await.unwrap()
+}
+
+#[actix_rt::test]
+async fn metrics_expose_the_subscription_funnel() {
+    let app = spawn_app().await;
+
+    create_confirmed_subscriber(&app).await;
+
+    let metrics = get_metrics(&app).await;
+    assert!(metrics.contains(r#"subscriptions_total{event="created"} 1"#));
+    assert!(metrics.contains(r#"subscriptions_total{event="confirmed"} 1"#));
+}
+
+#[actix_rt::test]
+async fn clicking_the_confirmation_link_again_is_not_counted() {
+    let app = spawn_app().await;
+    let confirmation_link = create_unconfirmed_subscriber(&app).await;
+
+    for _ in 0..2 {
+        reqwest::get(confirmation_link.html.clone())
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    let metrics = get_metrics(&app).await;
+    assert!(metrics.contains(r#"subscriptions_total{event="confirmed"} 1"#));
+}
+
+#[actix_rt::test]
+async fn metrics_count_requests_per_route() {
+    let app = spawn_app().await;
+
+    create_confirmed_subscriber(&app).await;
+
+    let metrics = get_metrics(&app).await;
+    assert!(metrics
+        .contains(r#"http_requests_total{method="POST",route="/subscriptions",status="200"} 1"#));
+    assert!(metrics.contains(
+        r#"http_requests_total{method="GET",route="/subscriptions/confirm",status="200"} 1"#
+    ));
+    assert!(metrics.contains(
+        r#"http_request_duration_seconds_count{method="POST",route="/subscriptions"} 1"#
+    ));
+}
+
+#[actix_rt::test]
+async fn metrics_report_the_queue_depth_and_email_outcomes() {
+    let app = spawn_app().await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let metrics = get_metrics(&app).await;
+    assert!(metrics.contains(r#"email_queue_depth{state="ready"} 1"#));
+    assert!(metrics.contains("db_pool_max_connections"));
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let metrics = get_metrics(&app).await;
+    assert!(metrics.contains(r#"email_queue_depth{state="ready"} 0"#));
+    assert!(metrics.contains(r#"emails_total{outcome="sent"} 1"#));
+}
@@ -1,7 +1,11 @@
-use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+use crate::helpers::{
+    batch_response, create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app,
+    spawn_app_with,
+};
 use reqwest::Client;
 use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
+use zero2prod::{email_client::MAX_BATCH_SIZE, runtime_settings::RuntimeSettings};
 
 #[actix_rt::test]
 async fn requests_missing_authorization_are_rejected() {
@@ -15,7 +19,7 @@ async fn requests_missing_authorization_are_rejected() {
     });
 
     let response = Client::new()
-        .post(&format!("{}/newsletters", &app.address))
+        .post(format!("{}/newsletters", &app.address))
         .json(&body)
         .send()
         .await
@@ -64,9 +68,9 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     let app = spawn_app().await;
     create_confirmed_subscriber(&app).await;
 
-    Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(batch_response(1))
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -107,35 +111,423 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     assert_eq!(200, response.status().as_u16());
 }
 
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
-    let _mock_guard = Mock::given(path("/email"))
+#[actix_rt::test]
+async fn newsletters_are_sent_one_by_one_when_batching_is_disabled() {
+    let app = spawn_app_with(|c| c.email_client.batch_enabled = false).await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
         .expect(1)
-        .mount_as_scoped(&app.email_server)
+        .mount(&app.email_server)
         .await;
-    app.post_subscriptions(body.into())
+
+    let newsletter_request_body = serde_json::json!({
+        "title":"Newsletter Title",
+        "content": {
+            "text":"Newsletter body as a plain text",
+            "html":"<p>Newsletter body as HTML</p>",
+        }
+    });
+
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn a_provider_failure_after_some_emails_went_out_does_not_fail_the_publish() {
+    let app = spawn_app_with(|c| c.email_client.batch_enabled = false).await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, 'octavia_butler@gmail.com', 'octavia butler', now(), 'confirmed')
+        "#,
+        uuid::Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title":"Newsletter Title",
+        "content": {
+            "text":"Newsletter body as a plain text",
+            "html":"<p>Newsletter body as HTML</p>",
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT recipients FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
         .await
-        .error_for_status()
-        .unwrap();
+        .expect("Failed to fetch saved newsletter issue.");
+    // The email that failed is queued for the worker, so it counts as well.
+    assert_eq!(2, saved.recipients);
+}
+
+#[actix_rt::test]
+async fn emails_of_a_batch_that_failed_with_a_500_are_retried_by_the_worker() {
+    let app = spawn_app().await;
+    // One more subscriber than fits a batch, so the issue goes out in two.
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        SELECT gen_random_uuid(), 'reader_' || n || '@example.com', 'reader', now(), 'confirmed'
+        FROM generate_series(1, $1::int) AS n
+        "#,
+        MAX_BATCH_SIZE as i32 + 1
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    Mock::given(path("/email/batch"))
+        .respond_with(batch_response(MAX_BATCH_SIZE))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email/batch"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title":"Newsletter Title",
+        "content": {
+            "text":"Newsletter body as a plain text",
+            "html":"<p>Newsletter body as HTML</p>",
+        },
+        "tag": "weekly",
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT recipients FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved newsletter issue.");
+    assert_eq!(MAX_BATCH_SIZE as i32 + 1, saved.recipients);
 
-    let email_request = &app
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let request = app
         .email_server
         .received_requests()
         .await
         .unwrap()
         .pop()
         .unwrap();
-    app.get_confirmation_links(&email_request)
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!("Newsletter Title", body["Subject"]);
+    assert_eq!("weekly", body["Tag"]);
+    let queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM email_outbox"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(0, queued.count);
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_link = create_unconfirmed_subscriber(app).await;
-    reqwest::get(confirmation_link.html)
+#[actix_rt::test]
+async fn publishing_fails_if_the_provider_is_down_before_any_email_went_out() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title":"Newsletter Title",
+        "content": {
+            "text":"Newsletter body as a plain text",
+            "html":"<p>Newsletter body as HTML</p>",
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+
+    assert_eq!(500, response.status().as_u16());
+    let saved = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
         .await
+        .unwrap();
+    assert_eq!(0, saved.count);
+}
+
+#[actix_rt::test]
+async fn publishing_does_not_wait_for_an_exhausted_send_budget() {
+    let app = spawn_app_with(|c| c.email_client.rate_limit.per_hour = Some(1)).await;
+    // The confirmation email spends the only email of the hour.
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title":"Newsletter Title",
+        "content": {
+            "text":"Newsletter body as a plain text",
+            "html":"<p>Newsletter body as HTML</p>",
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+
+    assert_eq!(429, response.status().as_u16());
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
         .unwrap()
-        .error_for_status()
+        .parse()
         .unwrap();
+    assert!(retry_after > 3500);
+}
+
+#[actix_rt::test]
+async fn recipients_rejected_by_the_batch_endpoint_are_not_counted() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            {"ErrorCode": 406, "Message": "You tried to send to a recipient that has been marked as inactive."}
+        ])))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title":"Newsletter Title",
+        "content": {
+            "text":"Newsletter body as a plain text",
+            "html":"<p>Newsletter body as HTML</p>",
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT recipients FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved newsletter issue.");
+    assert_eq!(0, saved.recipients);
+}
+
+#[actix_rt::test]
+async fn newsletter_options_are_forwarded_to_the_email_provider() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(batch_response(1))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title":"Newsletter Title",
+        "content": {
+            "text":"Newsletter body as a plain text",
+            "html":r#"<p>Newsletter body as HTML</p><img src="cid:logo">"#,
+        },
+        "reply_to": "editor@example.com",
+        "tag": "weekly",
+        "metadata": {"edition": "42"},
+        "attachments": [
+            {"name": "notes.txt", "content_type": "text/plain", "content": "aGVsbG8="},
+            {"name": "logo.png", "content_type": "image/png", "content": "iVBORw==", "content_id": "logo"},
+        ]
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let request = &app.email_server.received_requests().await.unwrap()[1];
+    let body: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    let message = &body[0];
+    assert_eq!("editor@example.com", message["ReplyTo"]);
+    assert_eq!("weekly", message["Tag"]);
+    assert_eq!("42", message["Metadata"]["edition"]);
+    assert_eq!("aGVsbG8=", message["Attachments"][0]["Content"]);
+    assert!(message["Attachments"][0].get("ContentID").is_none());
+    assert_eq!("cid:logo", message["Attachments"][1]["ContentID"]);
+}
+
+#[actix_rt::test]
+async fn newsletters_can_carry_attachments_up_to_the_provider_limit() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(batch_response(1))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let content = base64::encode(vec![0u8; 10 * 1024 * 1024]);
+    let newsletter_request_body = serde_json::json!({
+        "title":"Newsletter Title",
+        "content": {
+            "text":"Newsletter body as a plain text",
+            "html":"<p>Newsletter body as HTML</p>",
+        },
+        "attachments": [
+            {"name": "report.pdf", "content_type": "application/pdf", "content": content},
+        ]
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn newsletters_with_invalid_options_are_rejected() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let test_cases = vec![
+        (
+            serde_json::json!({"reply_to": "not-an-email"}),
+            "invalid reply-to",
+        ),
+        (
+            serde_json::json!({"attachments": [
+                {"name": "notes.txt", "content_type": "text/plain", "content": "%%%"}
+            ]}),
+            "attachment that is not base64",
+        ),
+        (
+            serde_json::json!({"metadata": {"a-key-that-is-far-too-long": "value"}}),
+            "metadata key that is too long",
+        ),
+    ];
+    for (options, error_message) in test_cases {
+        let mut body = serde_json::json!({
+            "title":"Newsletter Title",
+            "content": {
+                "text":"Newsletter body as a plain text",
+                "html":"<p>Newsletter body as HTML</p>",
+            }
+        });
+        body.as_object_mut()
+            .unwrap()
+            .extend(options.as_object().unwrap().clone());
+
+        let response = app.post_newsletters(body).await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request for an {}.",
+            error_message
+        );
+    }
+}
+
+#[actix_rt::test]
+async fn newsletters_can_be_sent_from_a_configured_sender_identity() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(batch_response(1))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title":"Newsletter Title",
+        "content": {
+            "text":"Newsletter body as a plain text",
+            "html":"<p>Newsletter body as HTML</p>",
+        },
+        "sender": "editorial",
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let request = &app.email_server.received_requests().await.unwrap()[1];
+    let body: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(r#""The Editors" <editor@gmail.com>"#, body[0]["From"]);
+    assert_eq!(r#""le guin" <ursula_le_guin@gmail.com>"#, body[0]["To"]);
+}
+
+#[actix_rt::test]
+async fn newsletters_from_an_unknown_sender_identity_are_rejected() {
+    let app = spawn_app().await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title":"Newsletter Title",
+        "content": {
+            "text":"Newsletter body as a plain text",
+            "html":"<p>Newsletter body as HTML</p>",
+        },
+        "sender": "marketing",
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn reloaded_settings_apply_to_the_running_application() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let settings = RuntimeSettings {
+        email_batch_enabled: false,
+        ..app.runtime_reloader.current()
+    };
+    let changes = app.runtime_reloader.apply(settings).await.unwrap();
+    assert_eq!(vec!["email_client.batch_enabled"], changes);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title":"Newsletter Title",
+        "content": {
+            "text":"Newsletter body as a plain text",
+            "html":"<p>Newsletter body as HTML</p>",
+        }
+    });
+
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(200, response.status().as_u16());
 }
@@ -0,0 +1,143 @@
+use crate::helpers::spawn_app;
+use reqwest::{Client, Response};
+
+/// Checks the RFC 7807 members of `response` and returns its body.
+async fn problem(response: Response, status: u16, code: &str) -> serde_json::Value {
+    assert_eq!(status, response.status().as_u16());
+    assert_eq!(
+        "application/problem+json",
+        response.headers()["Content-Type"]
+    );
+    let request_id = response.headers()["X-Request-Id"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("about:blank", body["type"]);
+    assert_eq!(status, body["status"]);
+    assert!(body["title"].is_string());
+    assert!(body["detail"].is_string());
+    assert_eq!(code, body["code"]);
+    assert_eq!(request_id, body["request_id"]);
+    body
+}
+
+#[actix_rt::test]
+async fn invalid_subscriber_names_are_explained() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions("name=%3Cscript%3E&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    let body = problem(response, 400, "invalid_subscriber").await;
+    assert_eq!("Bad Request", body["title"]);
+    assert!(body["detail"]
+        .as_str()
+        .unwrap()
+        .starts_with("The subscriber name contains"));
+}
+
+#[actix_rt::test]
+async fn missing_form_fields_are_problems() {
+    let app = spawn_app().await;
+
+    let response = app.post_subscriptions("name=le%20guin".into()).await;
+
+    let body = problem(response, 400, "invalid_form").await;
+    assert!(body["detail"].as_str().unwrap().contains("email"));
+}
+
+#[actix_rt::test]
+async fn malformed_json_is_a_problem() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({"title": "Newsletter title"}))
+        .await;
+
+    problem(response, 400, "invalid_json").await;
+}
+
+#[actix_rt::test]
+async fn confirmations_without_token_are_problems() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/subscriptions/confirm", &app.address))
+        .await
+        .unwrap();
+
+    problem(response, 400, "invalid_query").await;
+}
+
+#[actix_rt::test]
+async fn confirmations_with_an_unknown_token_are_problems() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/confirm?subscription_token=unknown",
+        &app.address
+    ))
+    .await
+    .unwrap();
+
+    problem(response, 401, "unknown_subscription_token").await;
+}
+
+#[actix_rt::test]
+async fn unknown_resources_are_problems() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/archive/no-such-issue", &app.address))
+        .await
+        .unwrap();
+    problem(response, 404, "newsletter_issue_not_found").await;
+
+    let response = app
+        .admin_request(
+            reqwest::Method::GET,
+            &format!("dead_letters/{}", uuid::Uuid::new_v4()),
+        )
+        .send()
+        .await
+        .unwrap();
+    problem(response, 404, "dead_letter_not_found").await;
+
+    let response = reqwest::get(format!("{}/no/such/route", &app.address))
+        .await
+        .unwrap();
+    problem(response, 404, "not_found").await;
+}
+
+#[actix_rt::test]
+async fn admin_authentication_failures_are_problems() {
+    let app = spawn_app().await;
+
+    let response = Client::new()
+        .get(format!("{}/admin/email_queue", &app.address))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        r#"Basic realm="admin""#,
+        response.headers()["WWW-Authenticate"]
+    );
+    problem(response, 401, "unauthorized").await;
+}
+
+#[actix_rt::test]
+async fn tampered_click_tokens_are_problems() {
+    let app = spawn_app().await;
+
+    let response = Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(format!("{}/t/c/not-a-token", &app.address))
+        .send()
+        .await
+        .unwrap();
+
+    problem(response, 400, "invalid_tracking_token").await;
+}
@@ -0,0 +1,117 @@
+use crate::helpers::spawn_app;
+use reqwest::Client;
+use uuid::Uuid;
+
+fn request_id(response: &reqwest::Response) -> &str {
+    response
+        .headers()
+        .get("X-Request-Id")
+        .expect("No X-Request-Id header.")
+        .to_str()
+        .unwrap()
+}
+
+#[actix_rt::test]
+async fn responses_carry_a_generated_request_id() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/health_check", &app.address))
+        .await
+        .unwrap();
+
+    assert!(Uuid::parse_str(request_id(&response)).is_ok());
+}
+
+#[actix_rt::test]
+async fn the_request_id_sent_by_the_client_is_returned() {
+    let app = spawn_app().await;
+
+    let response = Client::new()
+        .get(format!("{}/health_check", &app.address))
+        .header("X-Request-Id", "lb-7f3a9c")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!("lb-7f3a9c", request_id(&response));
+}
+
+#[actix_rt::test]
+async fn an_unusable_request_id_is_replaced() {
+    let app = spawn_app().await;
+
+    let response = Client::new()
+        .get(format!("{}/health_check", &app.address))
+        .header("X-Request-Id", "a".repeat(200))
+        .send()
+        .await
+        .unwrap();
+
+    assert!(Uuid::parse_str(request_id(&response)).is_ok());
+}
+
+#[actix_rt::test]
+async fn validation_errors_have_a_json_body_with_the_request_id() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=definitely-not-an-email".into())
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    let request_id = request_id(&response).to_owned();
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("invalid_subscriber", body["code"]);
+    assert_eq!(request_id, body["request_id"]);
+    assert_eq!(
+        "The subscriber email is not a valid email address.",
+        body["detail"]
+    );
+}
+
+#[actix_rt::test]
+async fn internal_errors_have_a_json_body_without_the_details() {
+    let app = spawn_app().await;
+    sqlx::query!("ALTER TABLE subscription_tokens DROP COLUMN subscription_token;",)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(500, response.status().as_u16());
+    let request_id = request_id(&response).to_owned();
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("store_token_failed", body["code"]);
+    assert_eq!(request_id, body["request_id"]);
+    assert!(!body["detail"]
+        .as_str()
+        .unwrap()
+        .contains("subscription_token"));
+}
+
+#[actix_rt::test]
+async fn authentication_failures_do_not_say_what_was_wrong() {
+    let app = spawn_app().await;
+
+    let response = Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(Uuid::new_v4().to_string(), Some("password"))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(401, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("unauthorized", body["code"]);
+    assert_eq!("Authentication failed.", body["detail"]);
+}
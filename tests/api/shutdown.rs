@@ -0,0 +1,85 @@
+use crate::helpers::{batch_response, create_confirmed_subscriber, spawn_app};
+use std::sync::Arc;
+use std::time::Duration;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::email_delivery_worker::{worker_loop, WorkerHeartbeat};
+use zero2prod::shutdown::Shutdown;
+
+#[actix_rt::test]
+async fn in_flight_requests_complete_when_the_server_stops() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(batch_response(1).set_delay(Duration::from_millis(500)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let in_flight = app.post_newsletters(serde_json::json!({
+        "title": "Newsletter Title",
+        "content": {
+            "text": "Newsletter body as a plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }));
+    let stop = async {
+        // Give the request time to reach the email provider.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        app.server_handle.stop(true).await;
+    };
+    let (response, ()) = tokio::join!(in_flight, stop);
+
+    assert_eq!(200, response.status().as_u16());
+    // The server no longer accepts requests.
+    assert!(reqwest::get(format!("{}/health_check", &app.address))
+        .await
+        .is_err());
+}
+
+#[actix_rt::test]
+async fn the_delivery_worker_finishes_its_current_email_before_stopping() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let shutdown = Shutdown::new();
+    let worker = tokio::spawn(worker_loop(
+        app.db_pool.clone(),
+        app.email_client.clone(),
+        app.email_outbox.clone(),
+        Arc::new(WorkerHeartbeat::default()),
+        shutdown.subscribe(),
+    ));
+
+    // Trigger the shutdown while the confirmation email is being delivered.
+    while app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty()
+    {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    shutdown.trigger();
+    tokio::time::timeout(Duration::from_secs(5), worker)
+        .await
+        .expect("The worker did not stop.")
+        .unwrap()
+        .unwrap();
+
+    let queued = sqlx::query!("SELECT COUNT(*) AS count FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(Some(0), queued.count);
+}
@@ -12,8 +12,9 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
         .mount(&app.email_server)
         .await;
     app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
     reqwest::get(confirmation_links.html)
         .await
         .unwrap()
@@ -33,7 +34,7 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
 async fn confirmations_without_token_are_rejected_with_a_400() {
     let app = spawn_app().await;
     let response = reqwest::Client::new()
-        .get(&format!("{}/subscriptions/confirm", &app.address))
+        .get(format!("{}/subscriptions/confirm", &app.address))
         .send()
         .await
         .expect("Failed to execute request.");
@@ -52,8 +53,9 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
         .await;
 
     let _response = app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
     let response = reqwest::Client::new()
         .get(confirmation_links.html)
         .send()
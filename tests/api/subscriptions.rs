@@ -1,5 +1,5 @@
-use crate::helpers::spawn_app;
-use wiremock::matchers::{method, path};
+use crate::helpers::{capture_logs, create_confirmed_subscriber, spawn_app, spawn_app_with};
+use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
 
 #[actix_rt::test]
@@ -28,8 +28,9 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
         .await;
 
     let _response = app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
     assert_eq!(confirmation_links.html, confirmation_links.plain_text);
 }
 
@@ -46,6 +47,7 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
         .await;
 
     let _response = app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
 }
 
 #[actix_rt::test]
@@ -78,6 +80,7 @@ async fn subscribe_returns_a_200_for_valid_form_data() {
         .await;
 
     let response = app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
 
     assert_eq!(200, response.status().as_u16());
 }
@@ -123,3 +126,271 @@ async fn subscribe_returns_a_200_when_fields_are_present_but_empty() {
         )
     }
 }
+
+#[actix_rt::test]
+async fn subscribe_succeeds_while_the_email_provider_is_down() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT recipient, n_retries FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the outbox entry.");
+    assert_eq!(saved.recipient, "ursula_le_guin@gmail.com");
+    assert_eq!(saved.n_retries, 0);
+}
+
+#[actix_rt::test]
+async fn confirmation_emails_are_not_written_if_the_transaction_fails() {
+    let app = spawn_app().await;
+    sqlx::query!("ALTER TABLE subscription_tokens DROP COLUMN subscription_token;",)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    let queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM email_outbox"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(0, queued.count);
+}
+
+#[actix_rt::test]
+async fn failed_confirmation_emails_are_retried() {
+    let app = spawn_app().await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let saved = sqlx::query!("SELECT n_retries, last_error FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the outbox entry.");
+    assert_eq!(1, saved.n_retries);
+    assert!(saved.last_error.is_some());
+
+    sqlx::query!("UPDATE email_outbox SET execute_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM email_outbox"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(0, queued.count);
+}
+
+#[actix_rt::test]
+async fn confirmation_emails_are_dead_lettered_after_the_last_attempt() {
+    let app = spawn_app_with(|c| c.email_outbox.max_attempts = 1).await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    sqlx::query!("UPDATE email_outbox SET execute_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM email_outbox"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(0, queued.count);
+    let saved = sqlx::query!("SELECT recipient, n_attempts, last_error FROM email_dead_letters")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the dead letter.");
+    assert_eq!(saved.recipient, "ursula_le_guin@gmail.com");
+    assert_eq!(1, saved.n_attempts);
+    assert!(!saved.last_error.is_empty());
+}
+
+#[actix_rt::test]
+async fn confirmation_emails_wait_for_an_open_circuit_without_using_up_attempts() {
+    let app = spawn_app_with(|c| {
+        c.email_outbox.max_attempts = 1;
+        c.email_client.circuit_breaker.failure_threshold = 1;
+    })
+    .await;
+    create_confirmed_subscriber(&app).await;
+    // A failed newsletter opens the circuit.
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter Title",
+        "content": {
+            "text": "Newsletter body as a plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await;
+
+    app.post_subscriptions("name=tolkien&email=jrr_tolkien%40gmail.com".into())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let saved = sqlx::query!("SELECT n_retries, execute_after FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the outbox entry.");
+    assert_eq!(0, saved.n_retries);
+    assert!(saved.execute_after > chrono::Utc::now());
+    let dead_letters = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM email_dead_letters"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(0, dead_letters.count);
+}
+
+#[actix_rt::test]
+async fn confirmation_emails_to_inactive_recipients_are_suppressed() {
+    let app = spawn_app().await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422).set_body_json(serde_json::json!({
+            "ErrorCode": 406,
+            "Message": "You tried to send to a recipient that has been marked as inactive."
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let queued = sqlx::query!(
+        r#"SELECT (SELECT COUNT(*) FROM email_outbox) + (SELECT COUNT(*) FROM email_dead_letters) AS "count!""#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(0, queued.count);
+}
+
+#[actix_rt::test]
+async fn rejected_confirmation_emails_are_dead_lettered_without_retrying() {
+    let app = spawn_app().await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422).set_body_json(serde_json::json!({
+            "ErrorCode": 412,
+            "Message": "Your account is pending approval."
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let saved = sqlx::query!("SELECT n_attempts, last_error FROM email_dead_letters")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the dead letter.");
+    assert_eq!(1, saved.n_attempts);
+    assert!(saved.last_error.contains("412"));
+}
+
+#[actix_rt::test]
+async fn malformed_requests_that_do_not_blame_the_recipient_are_dead_lettered() {
+    let app = spawn_app().await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422).set_body_json(serde_json::json!({
+            "ErrorCode": 300,
+            "Message": "Invalid 'From' address: 'no-reply'."
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let saved = sqlx::query!("SELECT n_attempts, last_error FROM email_dead_letters")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the dead letter.");
+    assert_eq!(1, saved.n_attempts);
+    assert!(saved.last_error.contains("300"));
+}
+
+#[actix_rt::test]
+async fn confirmation_emails_are_addressed_to_the_subscriber_by_name() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(r#""le guin" <ursula_le_guin@gmail.com>"#, body["To"]);
+    assert_eq!(r#""Zero To Production" <test@gmail.com>"#, body["From"]);
+}
+
+#[actix_rt::test]
+async fn a_duplicate_subscription_does_not_log_the_address() {
+    let logs = capture_logs();
+    let app = spawn_app().await;
+    let email = format!("{}@example.com", uuid::Uuid::new_v4());
+    let body = format!("name=le%20guin&email={}", email);
+
+    app.post_subscriptions(body.clone()).await;
+    let response = app.post_subscriptions(body).await;
+
+    assert_eq!(500, response.status().as_u16());
+    let logs = logs.contents();
+    assert!(logs.contains("Failed to execute query"));
+    assert!(!logs.contains(&email), "The address was logged");
+}
@@ -0,0 +1,194 @@
+use crate::helpers::{
+    batch_response, create_confirmed_subscriber, spawn_app, spawn_app_with, TestApp,
+};
+use reqwest::{redirect::Policy, Client};
+use wiremock::matchers::{method, path};
+use wiremock::Mock;
+use zero2prod::runtime_settings::RuntimeSettings;
+
+fn newsletter_body(tracking: bool) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter Title",
+        "content": {
+            "text": "Newsletter body as a plain text",
+            "html": r#"<p>Read <a href="https://example.com/post">the post</a></p>"#,
+        },
+        "tracking": tracking,
+    })
+}
+
+async fn publish(app: &TestApp, tracking: bool) -> (String, String) {
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(batch_response(1))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_newsletters(newsletter_body(tracking)).await;
+    assert_eq!(200, response.status().as_u16());
+    let published: serde_json::Value = response.json().await.unwrap();
+    let newsletter_issue_id = published["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html = body[0]["HtmlBody"].as_str().unwrap().to_owned();
+    (newsletter_issue_id, html)
+}
+
+fn tracking_link(app: &TestApp, html: &str, prefix: &str) -> reqwest::Url {
+    let start = html.find(prefix).expect("No tracking link found.");
+    let end = html[start..].find('"').unwrap();
+    let mut link = reqwest::Url::parse(&html[start..start + end]).unwrap();
+    link.set_port(Some(app.port)).unwrap();
+    link
+}
+
+#[actix_rt::test]
+async fn tracked_newsletters_rewrite_links_and_embed_a_pixel() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let (_, html) = publish(&app, true).await;
+
+    assert!(!html.contains("https://example.com/post"));
+    assert!(html.contains("http://127.0.0.1/t/c/"));
+    assert!(html.contains("http://127.0.0.1/t/o/"));
+}
+
+#[actix_rt::test]
+async fn untracked_newsletters_are_sent_unchanged() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let (_, html) = publish(&app, false).await;
+
+    assert_eq!(
+        html,
+        r#"<p>Read <a href="https://example.com/post">the post</a></p>"#
+    );
+}
+
+#[actix_rt::test]
+async fn tracking_can_be_disabled_entirely_from_configuration() {
+    let app = spawn_app_with(|c| c.tracking.enabled = false).await;
+    create_confirmed_subscriber(&app).await;
+
+    let (_, html) = publish(&app, true).await;
+
+    assert!(!html.contains("/t/c/"));
+    assert!(!html.contains("/t/o/"));
+}
+
+#[actix_rt::test]
+async fn opens_and_clicks_are_reported_in_the_issue_stats() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (newsletter_issue_id, html) = publish(&app, true).await;
+    let client = Client::builder().redirect(Policy::none()).build().unwrap();
+
+    let pixel = client
+        .get(tracking_link(&app, &html, "http://127.0.0.1/t/o/"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, pixel.status().as_u16());
+    assert_eq!("image/gif", pixel.headers()["Content-Type"]);
+
+    let click = client
+        .get(tracking_link(&app, &html, "http://127.0.0.1/t/c/"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(302, click.status().as_u16());
+    assert_eq!("https://example.com/post", click.headers()["Location"]);
+
+    let stats: serde_json::Value = app
+        .get_newsletter_issue_stats(&newsletter_issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(1, stats["recipients"]);
+    assert_eq!(1, stats["unique_opens"]);
+    assert_eq!(1, stats["unique_clicks"]);
+    assert_eq!(1.0, stats["open_rate"]);
+    assert_eq!(1.0, stats["click_rate"]);
+}
+
+#[actix_rt::test]
+async fn no_events_are_stored_once_tracking_is_disabled() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (newsletter_issue_id, html) = publish(&app, true).await;
+    let settings = RuntimeSettings {
+        tracking_enabled: false,
+        ..app.runtime_reloader.current()
+    };
+    app.runtime_reloader.apply(settings).await.unwrap();
+    let client = Client::builder().redirect(Policy::none()).build().unwrap();
+
+    let pixel = client
+        .get(tracking_link(&app, &html, "http://127.0.0.1/t/o/"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, pixel.status().as_u16());
+    let click = client
+        .get(tracking_link(&app, &html, "http://127.0.0.1/t/c/"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(302, click.status().as_u16());
+    assert_eq!("https://example.com/post", click.headers()["Location"]);
+
+    let stats: serde_json::Value = app
+        .get_newsletter_issue_stats(&newsletter_issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(0, stats["unique_opens"]);
+    assert_eq!(0, stats["unique_clicks"]);
+}
+
+#[actix_rt::test]
+async fn clicks_with_a_tampered_token_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = Client::builder()
+        .redirect(Policy::none())
+        .build()
+        .unwrap()
+        .get(format!("{}/t/c/bm90LWEtdG9rZW4.c2lnbmF0dXJl", &app.address))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn issue_stats_require_authorization() {
+    let app = spawn_app().await;
+
+    let response = Client::new()
+        .get(format!(
+            "{}/newsletters/{}/stats",
+            &app.address,
+            uuid::Uuid::new_v4()
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(401, response.status().as_u16());
+}